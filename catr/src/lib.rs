@@ -1,21 +1,241 @@
+use bzip2::read::MultiBzDecoder;
 use clap::{App, Arg};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use std::env;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::as_24_bit_terminal_escaped;
+use xz2::read::XzDecoder;
+use zip::{CompressionMethod, ZipArchive};
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
     number_lines: bool,
     number_nonblank_lines: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
+    squeeze_blank: bool,
+    pretty: bool,
+    headers: bool,
+}
+
+/// Where the output is within the current line, carried across reads and files
+#[derive(Debug, Default)]
+struct LineState {
+    line_number: usize,
+    mid_line: bool,   // the last byte written was not a newline
+    prev_blank: bool, // the last complete line was empty
+}
+
+/// Signs that a file is not plain UTF-8 with consistent line endings
+#[derive(Debug, Default)]
+struct EncodingCheck {
+    offset: usize,             // bytes seen so far
+    pending: Vec<u8>,          // a UTF-8 sequence cut off by the end of the last read
+    invalid_at: Option<usize>, // offset of the first byte that is not UTF-8
+    bom: Option<&'static str>, // the byte order mark the file starts with
+    prev_cr: bool,             // the last byte seen was a carriage return
+    crlf: usize,
+    lf: usize,
+}
+
+/// The bundled grammars and color theme used by --pretty
+struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+/// Compressed formats that are decoded on the fly, like zcat
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+/// An opened file, left as a bare file when nothing needs decoding
+enum Input {
+    File(BufReader<File>),
+    Decoded(Box<dyn BufRead>),
 }
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
+const BUFFER_SIZE: usize = 128 * 1024;
+
 fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
     match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+        "-" => Ok(Box::new(BufReader::with_capacity(BUFFER_SIZE, io::stdin()))),
+        _ => Ok(open_input(filename)?.into_reader()),
+    }
+}
+
+// Open a file, a compressed file or a member given as archive.tar:path/inside
+fn open_input(filename: &str) -> MyResult<Input> {
+    if let Some((archive, member)) = split_member(filename) {
+        return open_member(archive, member).map(Input::Decoded);
+    }
+
+    let mut file = BufReader::with_capacity(BUFFER_SIZE, File::open(filename)?);
+    // peeking with fill_buf leaves the header in place for the decoder
+    match Compression::detect(filename, file.fill_buf()?) {
+        Some(compression) => Ok(Input::Decoded(compression.decoder(file)?)),
+        None => Ok(Input::File(file)),
+    }
+}
+
+impl Compression {
+    // The extension names the format and the magic bytes confirm it, so
+    // anything else passes through untouched like `zcat -f`
+    fn detect(filename: &str, header: &[u8]) -> Option<Compression> {
+        Compression::from_extension(filename).filter(|compression| {
+            let magic: &[u8] = match compression {
+                Compression::Gzip => b"\x1f\x8b",
+                Compression::Bzip2 => b"BZh",
+                Compression::Xz => b"\xfd7zXZ\x00",
+                Compression::Zstd => b"\x28\xb5\x2f\xfd",
+            };
+            header.starts_with(magic)
+        })
+    }
+
+    fn from_extension(filename: &str) -> Option<Compression> {
+        const EXTENSIONS: [(&str, Compression); 5] = [
+            (".gz", Compression::Gzip),
+            (".tgz", Compression::Gzip),
+            (".bz2", Compression::Bzip2),
+            (".xz", Compression::Xz),
+            (".zst", Compression::Zstd),
+        ];
+        EXTENSIONS
+            .into_iter()
+            .find(|(ext, _)| filename.ends_with(ext))
+            .map(|(_, compression)| compression)
+    }
+
+    fn decoder(self, file: impl BufRead + 'static) -> MyResult<Box<dyn BufRead>> {
+        // concatenated streams are read through to the end, as zcat does
+        let reader: Box<dyn Read> = match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(file)),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(file)),
+            Compression::Zstd => Box::new(ZstdDecoder::with_buffer(file)?),
+        };
+        Ok(Box::new(BufReader::with_capacity(BUFFER_SIZE, reader)))
+    }
+}
+
+// Split archive.tar:path/inside into the archive and the member, unless a
+// file by the whole name exists
+fn split_member(filename: &str) -> Option<(&str, &str)> {
+    const ARCHIVES: [&str; 7] = [
+        ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tar.xz", ".tar.zst", ".zip",
+    ];
+    if Path::new(filename).exists() {
+        return None;
+    }
+    filename
+        .match_indices(':')
+        .map(|(i, _)| (&filename[..i], &filename[i + 1..]))
+        .find(|(archive, _)| {
+            ARCHIVES.iter().any(|ext| archive.ends_with(ext)) && Path::new(archive).is_file()
+        })
+}
+
+// Stream a single member straight out of the archive without extracting anything
+fn open_member(archive: &str, member: &str) -> MyResult<Box<dyn BufRead>> {
+    if archive.ends_with(".zip") {
+        return open_zip_member(archive, member);
+    }
+    let (start, size) = find_tar_member(archive, member)?;
+
+    let mut file = BufReader::with_capacity(BUFFER_SIZE, File::open(archive)?);
+    let reader: Box<dyn BufRead> = match Compression::detect(archive, file.fill_buf()?) {
+        None => {
+            file.seek(SeekFrom::Start(start))?;
+            Box::new(file)
+        }
+        // a compressed stream can only be skipped through
+        Some(compression) => {
+            let mut reader = compression.decoder(file)?;
+            io::copy(&mut (&mut reader).take(start), &mut io::sink())?;
+            reader
+        }
+    };
+    Ok(Box::new(reader.take(size)))
+}
+
+// Find where a regular file's data lies in the unpacked tar stream
+fn find_tar_member(archive: &str, member: &str) -> MyResult<(u64, u64)> {
+    let mut tar = tar::Archive::new(open_input(archive)?.into_reader());
+    let member = member_path(Path::new(member));
+    let mut found = None;
+    for entry in tar.entries()? {
+        let entry = entry?;
+        // as with tar itself, a member appended later replaces an earlier one
+        if entry.header().entry_type().is_file() && member_path(&entry.path()?) == member {
+            found = Some((entry.raw_file_position(), entry.size()));
+        }
+    }
+    found.ok_or_else(|| "no such member in the archive".into())
+}
+
+// Archives may list a member as ./name or plain name, so both sides drop any ./ parts
+fn member_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|part| *part != Component::CurDir)
+        .collect()
+}
+
+fn open_zip_member(archive: &str, member: &str) -> MyResult<Box<dyn BufRead>> {
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let member = member_path(Path::new(member));
+    let index = (0..zip.len())
+        .find(|&i| {
+            zip.by_index_raw(i)
+                .is_ok_and(|file| member_path(Path::new(file.name())) == member)
+        })
+        .ok_or("no such member in the archive")?;
+    // opening it normally rejects encrypted members and unknown compression
+    let (method, start, size) = {
+        let file = zip.by_index(index)?;
+        (
+            file.compression(),
+            file.data_start(),
+            file.compressed_size(),
+        )
+    };
+
+    let mut file = zip.into_inner();
+    file.seek(SeekFrom::Start(start))?;
+    let data = BufReader::with_capacity(BUFFER_SIZE, file.take(size));
+    match method {
+        CompressionMethod::Stored => Ok(Box::new(data)),
+        CompressionMethod::Deflated => Ok(Box::new(BufReader::with_capacity(
+            BUFFER_SIZE,
+            DeflateDecoder::new(data),
+        ))),
+        _ => Err(format!("unsupported compression method {}", method).into()),
+    }
+}
+
+impl Input {
+    fn into_reader(self) -> Box<dyn BufRead> {
+        match self {
+            Input::File(file) => Box::new(file),
+            Input::Decoded(reader) => reader,
+        }
     }
 }
 
@@ -27,10 +247,37 @@ pub fn get_args() -> MyResult<Config> {
         .arg(
             Arg::with_name("files")
                 .value_name("FILE")
-                .help("Input file(s)")
+                .help("Input file(s), decompressed if .gz/.bz2/.xz/.zst, or ARCHIVE.tar:MEMBER / ARCHIVE.zip:MEMBER")
                 .multiple(true)
                 .default_value("-"),
         )
+        .arg(
+            Arg::with_name("show_all")
+                .short("A")
+                .long("show-all")
+                .help("Equivalent to -vET")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("number_nonblank")
+                .short("b")
+                .long("number-nonblank")
+                .help("Number nonblank lines")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("e")
+                .short("e")
+                .help("Equivalent to -vE")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("show_ends")
+                .short("E")
+                .long("show-ends")
+                .help("Display $ at the end of each line")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("number")
                 .short("n")
@@ -40,47 +287,528 @@ pub fn get_args() -> MyResult<Config> {
                 .conflicts_with("number_nonblank"),
         )
         .arg(
-            Arg::with_name("number_nonblank")
-                .short("b")
-                .long("number-nonblank")
-                .help("Number nonblank lines")
+            Arg::with_name("squeeze_blank")
+                .short("s")
+                .long("squeeze-blank")
+                .help("Suppress repeated empty lines")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("t")
+                .short("t")
+                .help("Equivalent to -vT")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("show_tabs")
+                .short("T")
+                .long("show-tabs")
+                .help("Display TAB characters as ^I")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("unbuffered")
+                .short("u")
+                .help("(ignored)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("headers")
+                .long("headers")
+                .help("Print a ==> name <== header before each file and warn about its encoding")
+                .takes_value(false)
+                .conflicts_with("pretty"),
+        )
+        .arg(
+            Arg::with_name("pretty")
+                .value_name("WHEN")
+                .long("pretty")
+                .help("Syntax highlight with a header and line numbers, paged on a terminal")
+                .possible_values(&["auto", "always"])
+                .min_values(0)
+                .require_equals(true)
+                .conflicts_with_all(&[
+                    "show_all",
+                    "e",
+                    "show_ends",
+                    "t",
+                    "show_tabs",
+                    "show_nonprinting",
+                ]),
+        )
+        .arg(
+            Arg::with_name("show_nonprinting")
+                .short("v")
+                .long("show-nonprinting")
+                .help("Use ^ and M- notation, except for LFD and TAB")
                 .takes_value(false),
         )
         .get_matches();
 
+    let show_all = matches.is_present("show_all");
     Ok(Config {
         files: matches.values_of_lossy("files").unwrap(),
         number_lines: matches.is_present("number"),
         number_nonblank_lines: matches.is_present("number_nonblank"),
+        show_ends: show_all || matches.is_present("e") || matches.is_present("show_ends"),
+        show_tabs: show_all || matches.is_present("t") || matches.is_present("show_tabs"),
+        show_nonprinting: show_all
+            || matches.is_present("e")
+            || matches.is_present("t")
+            || matches.is_present("show_nonprinting"),
+        squeeze_blank: matches.is_present("squeeze_blank"),
+        // a pipe or file gets the plain bytes unless highlighting is forced
+        pretty: matches.is_present("pretty")
+            && (matches.value_of("pretty") == Some("always") || io::stdout().is_terminal()),
+        headers: matches.is_present("headers"),
     })
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    for filename in config.files {
-        match open(&filename) {
-            Err(err) => eprintln!("Failed to open {}: {}", filename, err),
-            Ok(file) => {
-                if config.number_lines {
-                    for (line_number, line_result) in file.lines().enumerate() {
-                        println!("{:>6}\t{}", line_number + 1, line_result?);
-                    }
-                } else if config.number_nonblank_lines {
-                    let mut line_number = 1;
-                    for line_result in file.lines() {
-                        let line = line_result?;
-                        if !line.is_empty() {
-                            print!("{:>6}\t", line_number);
-                            line_number += 1;
+// Returns the exit status: 1 when any file could not be opened or read
+pub fn run(config: Config) -> MyResult<i32> {
+    // kept apart from the result so a failure still counts when output stops early
+    let mut all_read = true;
+    let result = if config.pretty {
+        run_pretty(&config, &mut all_read)
+    } else {
+        write_files(&config, &mut all_read)
+    };
+    match result {
+        Ok(()) => {}
+        // whoever reads the output has stopped, as in `catr big | head`
+        Err(e)
+            if e.downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) => {}
+        Err(e) => return Err(e),
+    }
+    Ok(if all_read { 0 } else { 1 })
+}
+
+// Write every file to stdout, clearing `all_read` if any could not be read
+fn write_files(config: &Config, all_read: &mut bool) -> MyResult<()> {
+    let mut out = BufWriter::with_capacity(BUFFER_SIZE, io::stdout().lock());
+    let formatted = config.number_lines
+        || config.number_nonblank_lines
+        || config.show_ends
+        || config.show_tabs
+        || config.show_nonprinting
+        || config.squeeze_blank
+        || config.headers;
+    // numbering and blank-line squeezing carry on from one file into the next
+    let mut state = LineState::default();
+    // headers after the first are set apart by a blank line
+    let mut any_header = false;
+
+    for filename in &config.files {
+        if formatted {
+            match open(filename) {
+                Err(err) => {
+                    eprintln!("Failed to open {}: {}", filename, err);
+                    *all_read = false;
+                }
+                Ok(file) => {
+                    if config.headers {
+                        // a header always starts on a line of its own
+                        if state.mid_line {
+                            writeln!(out)?;
+                            state.mid_line = false;
                         }
-                        print!("{}\n", line);
-                    }
-                } else {
-                    for line_result in file.lines() {
-                        println!("{}", line_result?);
+                        writeln!(
+                            out,
+                            "{}==> {} <==",
+                            if any_header { "\n" } else { "" },
+                            filename
+                        )?;
+                        any_header = true;
                     }
+                    *all_read &= cat(file, filename, config, &mut state, &mut out)?;
+                }
+            }
+            continue;
+        }
+
+        *all_read &= match filename.as_str() {
+            "-" => copy(
+                BufReader::with_capacity(BUFFER_SIZE, io::stdin().lock()),
+                filename,
+                &mut out,
+            )?,
+            _ => match open_input(filename) {
+                Err(err) => {
+                    eprintln!("Failed to open {}: {}", filename, err);
+                    false
+                }
+                Ok(input) => copy(input.into_reader(), filename, &mut out)?,
+            },
+        };
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// Unformatted output is a plain copy through a large buffer. Reading and writing
+// are separate calls, so a read error is reported against the file and leaves the
+// rest unread, while a write error is returned.
+fn copy(mut reader: impl BufRead, filename: &str, out: &mut impl Write) -> MyResult<bool> {
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", filename, e);
+                return Ok(false);
+            }
+        };
+        if buf.is_empty() {
+            return Ok(true);
+        }
+        let len = buf.len();
+        out.write_all(buf)?;
+        reader.consume(len);
+    }
+}
+
+// Copy the input to `out` a read at a time, working on bytes so any encoding passes through.
+// A read error is reported against the file and leaves the rest unread, while a write
+// error is returned.
+fn cat(
+    mut file: impl BufRead,
+    filename: &str,
+    config: &Config,
+    state: &mut LineState,
+    out: &mut impl Write,
+) -> MyResult<bool> {
+    let mut check = config.headers.then(EncodingCheck::default);
+    loop {
+        let buf = match file.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", filename, e);
+                return Ok(false);
+            }
+        };
+        if buf.is_empty() {
+            break;
+        }
+        if let Some(check) = check.as_mut() {
+            check.update(buf);
+        }
+
+        // the last segment lacks a newline when a line continues into the next read
+        for segment in buf.split_inclusive(|&b| b == b'\n') {
+            let (content, newline) = match segment.strip_suffix(b"\n") {
+                Some(content) => (content, true),
+                None => (segment, false),
+            };
+            if !state.mid_line {
+                let blank = content.is_empty();
+                if state.squeezed(blank, config.squeeze_blank) {
+                    continue;
+                }
+                let number = state.number(blank, config.number_lines, config.number_nonblank_lines);
+                if let Some(number) = number {
+                    write!(out, "{:>6}\t", number)?;
+                }
+            }
+            write_visible(out, content, config)?;
+            if newline {
+                if config.show_ends {
+                    out.write_all(b"$")?;
                 }
+                out.write_all(b"\n")?;
             }
+            state.mid_line = !newline;
+        }
+
+        let len = buf.len();
+        file.consume(len);
+        // hand each read on right away so interactive input is echoed line by line
+        out.flush()?;
+    }
+
+    for warning in check.iter().flat_map(EncodingCheck::warnings) {
+        eprintln!("{}: warning: {}", filename, warning);
+    }
+    Ok(true)
+}
+
+impl EncodingCheck {
+    fn update(&mut self, bytes: &[u8]) {
+        if self.offset == 0 {
+            const BOMS: [(&[u8], &str); 3] = [
+                (b"\xef\xbb\xbf", "UTF-8"),
+                (b"\xff\xfe", "UTF-16LE"),
+                (b"\xfe\xff", "UTF-16BE"),
+            ];
+            self.bom = BOMS
+                .into_iter()
+                .find(|(bom, _)| bytes.starts_with(bom))
+                .map(|(_, name)| name);
+        }
+
+        for (i, _) in bytes.iter().enumerate().filter(|(_, &b)| b == b'\n') {
+            let cr = if i == 0 {
+                self.prev_cr
+            } else {
+                bytes[i - 1] == b'\r'
+            };
+            if cr {
+                self.crlf += 1;
+            } else {
+                self.lf += 1;
+            }
+        }
+        self.prev_cr = bytes.last() == Some(&b'\r');
+
+        if self.invalid_at.is_none() {
+            // only a sequence split across reads needs copying to be checked whole
+            let start = self.offset - self.pending.len();
+            let joined;
+            let text = if self.pending.is_empty() {
+                bytes
+            } else {
+                joined = [mem::take(&mut self.pending).as_slice(), bytes].concat();
+                &joined
+            };
+            if let Err(e) = std::str::from_utf8(text) {
+                match e.error_len() {
+                    None => self.pending = text[e.valid_up_to()..].to_vec(),
+                    Some(_) => self.invalid_at = Some(start + e.valid_up_to()),
+                }
+            }
+        }
+        self.offset += bytes.len();
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        // a sequence still unfinished at the end of the file is invalid too
+        let invalid_at = self
+            .invalid_at
+            .or_else(|| (!self.pending.is_empty()).then(|| self.offset - self.pending.len()));
+        if let Some(offset) = invalid_at {
+            warnings.push(format!(
+                "not valid UTF-8 (first bad byte at offset {})",
+                offset
+            ));
+        }
+        if let Some(bom) = self.bom {
+            warnings.push(format!("starts with a {} byte order mark", bom));
         }
+        if self.crlf > 0 && self.lf > 0 {
+            warnings.push(format!(
+                "mixes CRLF and LF line endings ({} CRLF, {} LF)",
+                self.crlf, self.lf
+            ));
+        }
+        warnings
+    }
+}
+
+impl LineState {
+    // With -s an empty line right after another is dropped
+    fn squeezed(&mut self, blank: bool, squeeze_blank: bool) -> bool {
+        let squeezed = blank && squeeze_blank && self.prev_blank;
+        self.prev_blank = blank;
+        squeezed
+    }
+
+    // -n numbers every line while -b skips the empty ones
+    fn number(&mut self, blank: bool, number_lines: bool, number_nonblank: bool) -> Option<usize> {
+        if number_lines || (number_nonblank && !blank) {
+            self.line_number += 1;
+            Some(self.line_number)
+        } else {
+            None
+        }
+    }
+}
+
+// Show each file highlighted in a grid like bat, through a pager when on a terminal
+fn run_pretty(config: &Config, all_read: &mut bool) -> MyResult<()> {
+    let highlighter = Highlighter {
+        syntaxes: SyntaxSet::load_defaults_newlines(),
+        theme: ThemeSet::load_defaults()
+            .themes
+            .remove("base16-ocean.dark")
+            .ok_or("missing the bundled theme")?,
+    };
+    let width = env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(80);
+
+    let mut pager = if io::stdout().is_terminal() {
+        spawn_pager()
+    } else {
+        None
+    };
+    let result = match pager.as_mut().and_then(|child| child.stdin.take()) {
+        Some(stdin) => write_pretty(config, &highlighter, width, BufWriter::new(stdin), all_read),
+        None => write_pretty(config, &highlighter, width, io::stdout().lock(), all_read),
+    };
+    // quitting the pager early is a broken pipe, which is not an error
+    if let Some(mut child) = pager {
+        child.wait()?;
+    }
+    result
+}
+
+// Start $PAGER, or `less` told to pass colors through and exit if the text fits
+fn spawn_pager() -> Option<Child> {
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -RF".to_string());
+    let mut words = pager.split_whitespace();
+    let program = words.next()?;
+    if program == "cat" {
+        return None;
+    }
+    Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()
+        .ok()
+}
+
+fn write_pretty(
+    config: &Config,
+    highlighter: &Highlighter,
+    width: usize,
+    mut out: impl Write,
+    all_read: &mut bool,
+) -> MyResult<()> {
+    for filename in &config.files {
+        *all_read &= match open(filename) {
+            Err(err) => {
+                eprintln!("Failed to open {}: {}", filename, err);
+                false
+            }
+            Ok(file) => pretty(file, filename, config, highlighter, width, &mut out)?,
+        };
     }
+    out.flush()?;
     Ok(())
 }
+
+fn pretty(
+    mut file: impl BufRead,
+    filename: &str,
+    config: &Config,
+    highlighter: &Highlighter,
+    width: usize,
+    out: &mut impl Write,
+) -> MyResult<bool> {
+    const GUTTER: usize = 7;
+    const GRID: &str = "\x1b[38;5;238m";
+    const RESET: &str = "\x1b[0m";
+    let rule = |joint: char| {
+        let rest = "─".repeat(width.saturating_sub(GUTTER + 1));
+        format!("{}{}{}{}{}", GRID, "─".repeat(GUTTER), joint, rest, RESET)
+    };
+
+    // the first line lets files like scripts without an extension be recognized
+    let mut line = vec![];
+    if let Err(e) = file.read_until(b'\n', &mut line) {
+        eprintln!("Failed to read {}: {}", filename, e);
+        return Ok(false);
+    }
+    let syntax = find_syntax(&highlighter.syntaxes, filename, &line);
+    let mut lines = HighlightLines::new(syntax, &highlighter.theme);
+
+    let name = if filename == "-" { "STDIN" } else { filename };
+    writeln!(out, "{}", rule('┬'))?;
+    writeln!(
+        out,
+        "{}{}│{} File: \x1b[1m{}{}",
+        GRID,
+        " ".repeat(GUTTER),
+        RESET,
+        name,
+        RESET
+    )?;
+    writeln!(out, "{}", rule('┼'))?;
+
+    // numbering starts over in each file since each has its own header
+    let mut state = LineState::default();
+    let mut all_read = true;
+    while !line.is_empty() {
+        let text = String::from_utf8_lossy(&line);
+        let blank = text.trim_end_matches(['\n', '\r']).is_empty();
+        if !state.squeezed(blank, config.squeeze_blank) {
+            let gutter = match state.number(blank, !config.number_nonblank_lines, true) {
+                Some(number) => format!("{:>4}", number),
+                None => String::new(),
+            };
+            let ranges = lines.highlight_line(&text, &highlighter.syntaxes)?;
+            let escaped = as_24_bit_terminal_escaped(&ranges, false);
+            // the reset goes before the newline so colors never leak into the gutter,
+            // which also closes the grid when the last line has no newline
+            writeln!(
+                out,
+                "{}{:<width$}│{} {}{}",
+                GRID,
+                gutter,
+                RESET,
+                escaped.trim_end_matches(['\n', '\r']),
+                RESET,
+                width = GUTTER
+            )?;
+        }
+        line.clear();
+        // the grid is still closed below whatever was read
+        if let Err(e) = file.read_until(b'\n', &mut line) {
+            eprintln!("Failed to read {}: {}", filename, e);
+            all_read = false;
+            break;
+        }
+    }
+
+    writeln!(out, "{}", rule('┴'))?;
+    Ok(all_read)
+}
+
+// Pick a grammar by file extension, then by the first line, else plain text
+fn find_syntax<'a>(
+    syntaxes: &'a SyntaxSet,
+    filename: &str,
+    first_line: &[u8],
+) -> &'a SyntaxReference {
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| syntaxes.find_syntax_by_extension(&ext.to_string_lossy()))
+        .or_else(|| syntaxes.find_syntax_by_first_line(&String::from_utf8_lossy(first_line)))
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+}
+
+// Write the bytes of a line, spelling out tabs and nonprinting bytes when asked
+fn write_visible(out: &mut impl Write, content: &[u8], config: &Config) -> io::Result<()> {
+    if !config.show_tabs && !config.show_nonprinting {
+        return out.write_all(content);
+    }
+
+    let mut visible = Vec::with_capacity(content.len());
+    for &byte in content {
+        match byte {
+            b'\t' if config.show_tabs => visible.extend_from_slice(b"^I"),
+            b'\t' => visible.push(byte),
+            _ if config.show_nonprinting => push_caret_notation(&mut visible, byte),
+            _ => visible.push(byte),
+        }
+    }
+    out.write_all(&visible)
+}
+
+// GNU cat's -v notation: M- for the high bit, then ^ for control characters and DEL
+fn push_caret_notation(dst: &mut Vec<u8>, byte: u8) {
+    let byte = if byte >= 128 {
+        dst.extend_from_slice(b"M-");
+        byte - 128
+    } else {
+        byte
+    };
+    match byte {
+        0..=31 => dst.extend_from_slice(&[b'^', byte + 64]),
+        127 => dst.extend_from_slice(b"^?"),
+        _ => dst.push(byte),
+    }
+}
@@ -3,6 +3,8 @@ use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
 use std::error::Error;
 use std::fs;
+use std::io::Read;
+use std::process::Stdio;
 
 type TestResult = Result<(), Box<dyn Error>>;
 
@@ -11,6 +13,15 @@ const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const SPIDERS: &str = "tests/inputs/spiders.txt";
 const BUSTLE: &str = "tests/inputs/the-bustle.txt";
+const NONPRINTING: &str = "tests/inputs/nonprinting.txt";
+const HELLO: &str = "tests/inputs/hello.rs";
+const MIXED: &str = "tests/inputs/mixed.txt";
+const TRUNCATED: &str = "tests/inputs/truncated.txt.gz";
+const ARCHIVES: [&str; 3] = [
+    "tests/inputs/archive.tar",
+    "tests/inputs/archive.tar.gz",
+    "tests/inputs/archive.zip",
+];
 
 // --------------------------------------------------
 #[test]
@@ -45,16 +56,17 @@ fn skips_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .arg(&bad)
+        .args([&bad, FOX])
         .assert()
-        .success()
+        .code(1)
+        .stdout(fs::read_to_string(FOX)?)
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
 }
 
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    let expected = fs::read_to_string(expected_file)?;
+    let expected = fs::read(expected_file)?;
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
@@ -196,3 +208,397 @@ fn all_n() -> TestResult {
 fn all_b() -> TestResult {
     run(&[FOX, SPIDERS, BUSTLE, "-b"], "tests/expected/all.b.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn nonprinting() -> TestResult {
+    run(&[NONPRINTING], "tests/expected/nonprinting.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_show_all() -> TestResult {
+    run(&["-A", NONPRINTING], "tests/expected/nonprinting.txt.A.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_show_all_long() -> TestResult {
+    run(
+        &[
+            "--show-nonprinting",
+            "--show-ends",
+            "--show-tabs",
+            NONPRINTING,
+        ],
+        "tests/expected/nonprinting.txt.A.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_show_all_n() -> TestResult {
+    run(
+        &["-A", "-n", NONPRINTING],
+        "tests/expected/nonprinting.txt.An.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_e() -> TestResult {
+    run(&["-e", NONPRINTING], "tests/expected/nonprinting.txt.e.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_show_ends() -> TestResult {
+    run(&["-E", NONPRINTING], "tests/expected/nonprinting.txt.E.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_t() -> TestResult {
+    run(&["-t", NONPRINTING], "tests/expected/nonprinting.txt.t.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_show_tabs() -> TestResult {
+    run(&["-T", NONPRINTING], "tests/expected/nonprinting.txt.T.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_v() -> TestResult {
+    run(&["-v", NONPRINTING], "tests/expected/nonprinting.txt.v.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_squeeze_blank() -> TestResult {
+    run(&["-s", NONPRINTING], "tests/expected/nonprinting.txt.s.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_squeeze_blank_n() -> TestResult {
+    run(
+        &["-s", "-n", NONPRINTING],
+        "tests/expected/nonprinting.txt.sn.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_squeeze_blank_b() -> TestResult {
+    run(
+        &["--squeeze-blank", "-b", NONPRINTING],
+        "tests/expected/nonprinting.txt.sb.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nonprinting_fox_n() -> TestResult {
+    // the unterminated last line runs on into the next file without a number
+    run(
+        &["-n", NONPRINTING, FOX],
+        "tests/expected/nonprinting.fox.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn concatenation_is_lossless() -> TestResult {
+    let mut expected = vec![];
+    for filename in [NONPRINTING, FOX, SPIDERS, NONPRINTING] {
+        expected.extend(fs::read(filename)?);
+    }
+    Command::cargo_bin(PRG)?
+        .args([NONPRINTING, FOX, "-", NONPRINTING])
+        .write_stdin(fs::read(SPIDERS)?)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+fn run_pretty(args: &[&str], expected_file: &str) -> TestResult {
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .env("COLUMNS", "40")
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hello_pretty() -> TestResult {
+    run_pretty(
+        &["--pretty=always", HELLO],
+        "tests/expected/hello.rs.pretty.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_pretty_b() -> TestResult {
+    run_pretty(
+        &["--pretty=always", "-b", BUSTLE],
+        "tests/expected/the-bustle.txt.pretty.b.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pretty_not_a_terminal() -> TestResult {
+    // output to a pipe falls back to the plain bytes
+    run(
+        &["--pretty", FOX, SPIDERS, BUSTLE],
+        "tests/expected/all.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pretty_conflicts_show_all() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--pretty=always", "-A", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn all_headers() -> TestResult {
+    // same layout as `head` and `headr` with several files
+    run(
+        &["--headers", FOX, SPIDERS, BUSTLE],
+        "tests/expected/all.headers.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn headers_start_on_a_new_line() -> TestResult {
+    let mut expected = format!("==> {} <==\n", NONPRINTING).into_bytes();
+    expected.extend(fs::read(NONPRINTING)?);
+    expected.extend(format!("\n\n==> {} <==\n", FOX).into_bytes());
+    expected.extend(fs::read(FOX)?);
+    Command::cargo_bin(PRG)?
+        .args(["--headers", NONPRINTING, FOX])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn headers_skip_bad_file() -> TestResult {
+    // no blank line before the first header that is actually written
+    let bad = gen_bad_file();
+    let mut expected = format!("==> {} <==\n", FOX).into_bytes();
+    expected.extend(fs::read(FOX)?);
+    Command::cargo_bin(PRG)?
+        .args(["--headers", &bad, FOX])
+        .assert()
+        .code(1)
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn headers_warn_about_encoding() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--headers", MIXED, NONPRINTING, FOX])
+        .assert()
+        .success()
+        .stderr(format!(
+            "{m}: warning: starts with a UTF-8 byte order mark\n\
+             {m}: warning: mixes CRLF and LF line endings (2 CRLF, 1 LF)\n\
+             {n}: warning: not valid UTF-8 (first bad byte at offset 39)\n",
+            m = MIXED,
+            n = NONPRINTING
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn headers_conflicts_pretty() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--pretty=always", "--headers", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_compressed() -> TestResult {
+    for ext in ["gz", "bz2", "xz", "zst"] {
+        let filename = format!("{}.{}", BUSTLE, ext);
+        run(&[&filename], "tests/expected/the-bustle.txt.out")?;
+        run(&["-n", &filename], "tests/expected/the-bustle.txt.n.out")?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn concatenated_gzip_streams() -> TestResult {
+    let mut expected = fs::read(FOX)?;
+    expected.extend(fs::read(SPIDERS)?);
+    Command::cargo_bin(PRG)?
+        .arg("tests/inputs/fox-spiders.txt.gz")
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn archive_members() -> TestResult {
+    for archive in ARCHIVES {
+        run(
+            &[
+                &format!("{}:fox.txt", archive),
+                &format!("{}:spiders.txt", archive),
+                &format!("{}:poems/the-bustle.txt", archive),
+                "-b",
+            ],
+            "tests/expected/all.b.out",
+        )?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn archive_members_dot_slash() -> TestResult {
+    // the tar archives list fox.txt as ./fox.txt and the zip as fox.txt
+    for archive in ARCHIVES {
+        run(
+            &[
+                &format!("{}:./fox.txt", archive),
+                &format!("{}:./spiders.txt", archive),
+                &format!("{}:./poems/the-bustle.txt", archive),
+                "-b",
+            ],
+            "tests/expected/all.b.out",
+        )?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn archive_missing_member() -> TestResult {
+    for archive in ARCHIVES {
+        Command::cargo_bin(PRG)?
+            .args([&format!("{}:poems", archive), FOX])
+            .assert()
+            .stdout(fs::read_to_string(FOX)?)
+            .stderr(predicate::str::contains("no such member in the archive"));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn read_error_names_the_file() -> TestResult {
+    // the rest of the file is lost but the files after it are still read
+    for flag in ["-u", "-n"] {
+        Command::cargo_bin(PRG)?
+            .args([flag, TRUNCATED, FOX])
+            .assert()
+            .code(1)
+            .stdout(predicate::str::contains("The bustle in a house"))
+            .stdout(predicate::str::ends_with(fs::read_to_string(FOX)?))
+            .stderr(format!(
+                "Failed to read {}: incomplete deflate stream\n",
+                TRUNCATED
+            ));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn broken_pipe_is_quiet() -> TestResult {
+    // far more output than a pipe holds, so writing outlasts the reader
+    let files = vec![BUSTLE; 20_000];
+    for flag in ["-u", "-n"] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .arg(flag)
+            .args(&files)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut start = [0; 10];
+        child
+            .stdout
+            .take()
+            .ok_or("no stdout")?
+            .read_exact(&mut start)?;
+
+        let output = child.wait_with_output()?;
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stderr)?, "");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn broken_pipe_keeps_failure() -> TestResult {
+    // a file that already failed still sets the status when output stops early
+    let bad = gen_bad_file();
+    let mut files = vec![BUSTLE; 20_000];
+    files.insert(0, &bad);
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(&files)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut start = [0; 10];
+    child
+        .stdout
+        .take()
+        .ok_or("no stdout")?
+        .read_exact(&mut start)?;
+
+    let output = child.wait_with_output()?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)?.starts_with(&format!("Failed to open {}", bad)));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn write_error_stops() -> TestResult {
+    // enough output to fill the write buffer while the files are still being copied
+    let files = vec![BUSTLE; 2_000];
+    let output = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(&files)
+        .stdout(fs::File::create("/dev/full")?)
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "No space left on device (os error 28)\n"
+    );
+    Ok(())
+}
@@ -1,22 +1,197 @@
-use clap::{App, Arg};
-use regex::{Regex, RegexBuilder};
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use ansi_term::{Colour, Style};
+use bzip2::bufread::MultiBzDecoder;
+use clap::{App, Arg, ErrorKind};
+use flate2::bufread::MultiGzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use regex::bytes::{Regex, RegexBuilder};
+use serde_json::{json, Value};
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env,
     error::Error,
     fs::{self, File},
-    io::{self, BufRead, BufReader},
+    io::{self, BufRead, BufReader, IsTerminal, Write},
     mem,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
-use walkdir::{DirEntry, WalkDir};
+use xz2::bufread::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
 #[derive(Debug)]
 pub struct Config {
-    pattern: Regex,
+    matcher: Matcher,
     files: Vec<String>,
-    recursive: bool,
+    walk: WalkOptions,
     count: bool,
     invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    line_number: bool,
+    byte_offset: bool,
+    only_matching: bool,
+    colors: Colors,
+    threads: usize,
+    sort_path: bool,
+    binary_files: BinaryFiles,
+    files_with_matches: bool,
+    files_without_match: bool,
+    max_count: Option<usize>,
+    quiet: bool,
+    json: bool,
+    search_zip: bool,
+    replace: Option<Vec<u8>>, // substitute this template for every match
+    in_place: bool,
+    dry_run: bool,
+    multiline: bool,
+    null_data: bool,
+    stats: bool,
+}
+
+/// How to treat files that look like binary data
+#[derive(Debug, PartialEq)]
+enum BinaryFiles {
+    Binary,       // report that a match was found without printing it
+    Text,         // search and print as if the file were text
+    WithoutMatch, // assume the file cannot match
+}
+
+/// Finds pattern matches within a single line
+#[derive(Debug)]
+struct Matcher {
+    engine: Engine,
+    word_regexp: bool, // only accept matches that form whole words
+    line_regexp: bool, // only accept a match spanning the whole line
+    multiline: bool,   // text holds many lines, so -x is left to the regex anchors
+}
+
+/// How `build_matcher` interprets the patterns
+#[derive(Debug, Default)]
+struct MatcherOptions {
+    fixed_strings: bool,
+    insensitive: bool,
+    word_regexp: bool,
+    line_regexp: bool,
+    multiline: bool, // let matches span lines and anchor ^ and $ to each line
+    perl: bool,      // use the backtracking engine for look-around and backreferences
+}
+
+#[derive(Debug)]
+enum Engine {
+    Regex(Regex),
+    Literal(Box<AhoCorasick>), // boxed since its automaton dwarfs a Regex
+    #[cfg(feature = "pcre")]
+    Fancy(fancy_regex::Regex),
+}
+
+/// Compressed formats that -z can decode
+#[derive(Debug, PartialEq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+/// Which files a recursive search descends into and selects
+#[derive(Debug, Default)]
+struct WalkOptions {
+    recursive: bool,
+    hidden: bool,
+    no_ignore: bool,
+    max_depth: Option<usize>,
+    include: GlobSet, // an empty set selects every file
+    exclude: GlobSet,
+}
+
+/// Styles for each part of the output, all plain when color is off
+#[derive(Debug, Default, PartialEq)]
+struct Colors {
+    matched: Style,
+    filename: Style,
+    line_num: Style,
+    byte_offset: Style,
+    separator: Style,
+}
+
+/// A line read from the input along with where it was found
+#[derive(Debug, PartialEq)]
+struct Record {
+    line_num: usize,            // 1-based line number
+    offset: usize,              // byte offset of the start of the line
+    text: Vec<u8>,              // the raw line including its line ending
+    spans: Vec<(usize, usize)>, // byte ranges of each pattern match within `text`
+}
+
+/// Per-file settings that control which lines `find_lines` emits
+#[derive(Debug)]
+struct SearchOptions {
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<usize>, // stop reading after this many matching lines
+    multiline: bool,          // match across the whole input rather than line by line
+    terminator: u8,           // the byte that ends each line
+    time_matching: bool,      // measure the time spent in the matcher, which has a cost per line
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            invert_match: false,
+            before_context: 0,
+            after_context: 0,
+            max_count: None,
+            multiline: false,
+            terminator: b'\n',
+            time_matching: false,
+        }
+    }
+}
+
+/// What searching one file produced
+#[derive(Debug)]
+struct Outcome {
+    output: Vec<u8>,
+    selected: bool, // whether the file counts as a success for the exit status
+    failed: bool,
+    hunks: bool, // whether `output` holds lines that a separator can divide from other files
+    stats: Stats,
+}
+
+/// Counters for a single file, summed across files for the summary
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    elapsed: Duration,
+    match_elapsed: Duration, // the part of `elapsed` spent running the matcher
+    matched_lines: usize,
+    matches: usize,
+    bytes_searched: usize,
+}
+
+/// A line that --in-place rewrote
+#[derive(Debug)]
+struct Change<'a> {
+    line_num: usize,
+    old: &'a [u8],
+    new: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Line {
+    Match(Record),
+    Context(Record),
+    Break,
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -28,7 +203,7 @@ pub fn get_args() -> MyResult<Config> {
             Arg::with_name("pattern")
                 .value_name("PATTERN")
                 .help("Search pattern")
-                .required(true),
+                .required_unless_one(&["regexp", "pattern_file"]),
         )
         .arg(
             Arg::with_name("file")
@@ -37,6 +212,55 @@ pub fn get_args() -> MyResult<Config> {
                 .multiple(true)
                 .default_value("-"),
         )
+        .arg(
+            Arg::with_name("regexp")
+                .value_name("PATTERN")
+                .help("Use PATTERN for matching, may be repeated")
+                .short("e")
+                .long("regexp")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("pattern_file")
+                .value_name("FILE")
+                .help("Read patterns from FILE, one per line")
+                .short("f")
+                .long("file")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("fixed_strings")
+                .help("Interpret patterns as literal strings")
+                .short("F")
+                .long("fixed-strings")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("perl_regexp")
+                .help("Use a backtracking engine that supports look-around and backreferences")
+                .short("P")
+                .long("perl-regexp")
+                .takes_value(false)
+                .conflicts_with("fixed_strings"),
+        )
+        .arg(
+            Arg::with_name("word_regexp")
+                .help("Only match whole words")
+                .short("w")
+                .long("word-regexp")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("line_regexp")
+                .help("Only match whole lines")
+                .short("x")
+                .long("line-regexp")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("count")
                 .help("Count occurrences")
@@ -65,143 +289,1528 @@ pub fn get_args() -> MyResult<Config> {
                 .long("recursive")
                 .takes_value(false),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("hidden")
+                .help("Search hidden files and directories")
+                .long("hidden")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no_ignore")
+                .help("Don't respect .gitignore, .ignore or global git excludes")
+                .long("no-ignore")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("include")
+                .value_name("GLOB")
+                .help("Only search files whose name matches GLOB")
+                .long("include")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .value_name("GLOB")
+                .help("Skip files whose name matches GLOB")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("max_depth")
+                .value_name("NUM")
+                .help("Descend at most NUM directories below each FILE")
+                .long("max-depth")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("binary_files")
+                .value_name("TYPE")
+                .help("How to handle binary files")
+                .long("binary-files")
+                .possible_values(&["binary", "text", "without-match"])
+                .default_value("binary"),
+        )
+        .arg(
+            Arg::with_name("text")
+                .help("Process a binary file as if it were text")
+                .short("a")
+                .long("text")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("files_with_matches")
+                .help("Print only the names of files with matches")
+                .short("l")
+                .long("files-with-matches")
+                .takes_value(false)
+                .conflicts_with("files_without_match"),
+        )
+        .arg(
+            Arg::with_name("files_without_match")
+                .help("Print only the names of files without matches")
+                .short("L")
+                .long("files-without-match")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("max_count")
+                .value_name("NUM")
+                .help("Stop reading a file after NUM matching lines")
+                .short("m")
+                .long("max-count")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .help("Print nothing, exit with zero status on any match")
+                .short("q")
+                .long("quiet")
+                .alias("silent")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("json")
+                .help("Print results as JSON Lines")
+                .long("json")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "count",
+                    "files_with_matches",
+                    "files_without_match",
+                    "quiet",
+                    "only_matching",
+                ]),
+        )
+        .arg(
+            Arg::with_name("search_zip")
+                .help("Search inside gzip, bzip2, xz and zstd compressed files")
+                .short("z")
+                .long("search-zip")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("replace")
+                .value_name("TEMPLATE")
+                .help("Print lines with matches replaced by TEMPLATE, which may use $1 or ${name}")
+                .long("replace")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("in_place")
+                .help("Rewrite files with matches replaced instead of printing them")
+                .long("in-place")
+                .takes_value(false)
+                .requires("replace")
+                .conflicts_with_all(&[
+                    "count",
+                    "quiet",
+                    "invert-match",
+                    "files_with_matches",
+                    "files_without_match",
+                    "json",
+                    "search_zip",
+                    "multiline",
+                    "null_data",
+                ]),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .help("With --in-place, print a diff of the changes without writing them")
+                .long("dry-run")
+                .takes_value(false)
+                .requires("in_place"),
+        )
+        .arg(
+            Arg::with_name("multiline")
+                .help("Allow matches to span lines, printing every line a match touches")
+                .short("U")
+                .long("multiline")
+                .takes_value(false)
+                .conflicts_with("replace"),
+        )
+        .arg(
+            Arg::with_name("null_data")
+                .help("Lines are terminated by a NUL byte rather than a newline")
+                .long("null-data")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .help("Print statistics about the search when it is done")
+                .long("stats")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("after_context")
+                .value_name("NUM")
+                .help("Print NUM lines of trailing context")
+                .short("A")
+                .long("after-context")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("before_context")
+                .value_name("NUM")
+                .help("Print NUM lines of leading context")
+                .short("B")
+                .long("before-context")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("context")
+                .value_name("NUM")
+                .help("Print NUM lines of output context")
+                .short("C")
+                .long("context")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("line_number")
+                .help("Prefix each line with its line number")
+                .short("n")
+                .long("line-number")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("byte_offset")
+                .help("Prefix each line with its byte offset")
+                .short("b")
+                .long("byte-offset")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("only_matching")
+                .help("Print only the matched parts of a line")
+                .short("o")
+                .long("only-matching")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("color")
+                .value_name("WHEN")
+                .help("Highlight matches")
+                .long("color")
+                .alias("colour")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .value_name("NUM")
+                .help("Number of files to search in parallel [default: CPU count]")
+                .short("j")
+                .long("threads")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .value_name("SORTBY")
+                .help("Print results in sorted order")
+                .long("sort")
+                .possible_values(&["none", "path"])
+                .default_value("none"),
+        )
+        .get_matches_safe()
+        // usage errors are reported by main with grep's exit status of 2
+        .or_else(|e| match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => Err(e),
+        })?;
+
+    // with -e or -f the first positional argument is really a file
+    let mut files = matches.values_of_lossy("file").unwrap();
+    let mut patterns = matches.values_of_lossy("regexp").unwrap_or_default();
+    if let Some(pattern_files) = matches.values_of_lossy("pattern_file") {
+        for filename in pattern_files {
+            let contents =
+                fs::read_to_string(&filename).map_err(|e| format!("{}: {}", filename, e))?;
+            patterns.extend(contents.lines().map(String::from));
+        }
+    }
+    if let Some(input) = matches.value_of("pattern") {
+        if matches.is_present("regexp") || matches.is_present("pattern_file") {
+            if matches.occurrences_of("file") == 0 {
+                files.clear();
+            }
+            files.insert(0, input.to_string());
+        } else {
+            patterns.push(input.to_string());
+        }
+    }
 
-    let input = matches.value_of("pattern").unwrap();
-    let pattern = RegexBuilder::new(input)
-        .case_insensitive(matches.is_present("insensitive"))
-        .build()
-        .map_err(|_| format!("Invalid pattern \"{}\"", input))?;
+    let matcher = build_matcher(
+        &patterns,
+        &MatcherOptions {
+            fixed_strings: matches.is_present("fixed_strings"),
+            insensitive: matches.is_present("insensitive"),
+            word_regexp: matches.is_present("word_regexp"),
+            line_regexp: matches.is_present("line_regexp"),
+            multiline: matches.is_present("multiline"),
+            perl: matches.is_present("perl_regexp"),
+        },
+    )?;
 
-    Ok(Config {
-        pattern,
-        files: matches.values_of_lossy("file").unwrap(),
+    // -A and -B take precedence over the symmetric -C value
+    let context = parse_context(matches.value_of("context"))?.unwrap_or(0);
+    let before_context = parse_context(matches.value_of("before_context"))?.unwrap_or(context);
+    let after_context = parse_context(matches.value_of("after_context"))?.unwrap_or(context);
+
+    let threads = match matches.value_of("threads") {
+        Some(val) => val
+            .parse()
+            .map_err(|_| format!("{}: invalid thread count", val))?,
+        None => sys_info::cpu_num().map_or(1, |n| n as usize),
+    };
+
+    let max_depth = matches
+        .value_of("max_depth")
+        .map(|val| {
+            val.parse()
+                .map_err(|_| format!("{}: invalid max depth", val))
+        })
+        .transpose()?;
+    let walk = WalkOptions {
         recursive: matches.is_present("recursive"),
+        hidden: matches.is_present("hidden"),
+        no_ignore: matches.is_present("no_ignore"),
+        max_depth,
+        include: build_globs(matches.values_of_lossy("include"))?,
+        exclude: build_globs(matches.values_of_lossy("exclude"))?,
+    };
+
+    let max_count = matches
+        .value_of("max_count")
+        .map(|val| {
+            val.parse()
+                .map_err(|_| format!("{}: invalid max count", val))
+        })
+        .transpose()?;
+
+    let binary_files = match matches.value_of("binary_files") {
+        _ if matches.is_present("text") => BinaryFiles::Text,
+        Some("text") => BinaryFiles::Text,
+        Some("without-match") => BinaryFiles::WithoutMatch,
+        _ => BinaryFiles::Binary,
+    };
+
+    Ok(Config {
+        matcher,
+        files,
+        walk,
         count: matches.is_present("count"),
         invert_match: matches.is_present("invert-match"),
+        before_context,
+        after_context,
+        line_number: matches.is_present("line_number"),
+        byte_offset: matches.is_present("byte_offset"),
+        only_matching: matches.is_present("only_matching"),
+        colors: get_colors(matches.value_of("color").unwrap()),
+        threads,
+        sort_path: matches.value_of("sort") == Some("path"),
+        binary_files,
+        files_with_matches: matches.is_present("files_with_matches"),
+        files_without_match: matches.is_present("files_without_match"),
+        max_count,
+        quiet: matches.is_present("quiet"),
+        json: matches.is_present("json"),
+        search_zip: matches.is_present("search_zip"),
+        replace: matches.value_of("replace").map(|t| t.as_bytes().to_vec()),
+        in_place: matches.is_present("in_place"),
+        dry_run: matches.is_present("dry_run"),
+        multiline: matches.is_present("multiline"),
+        null_data: matches.is_present("null_data"),
+        stats: matches.is_present("stats"),
     })
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let entries = find_files(&config.files, config.recursive);
-    let num_files = entries.len();
-    let print = |fname: &str, val: &str| {
-        if num_files > 1 {
-            print!("{}:{}", fname, val);
-        } else {
-            print!("{}", val);
-        }
+fn build_globs(globs: Option<Vec<String>>) -> MyResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs.unwrap_or_default() {
+        builder.add(Glob::new(&glob).map_err(|_| format!("Invalid glob \"{}\"", glob))?);
+    }
+    Ok(builder.build()?)
+}
+
+fn get_colors(when: &str) -> Colors {
+    // NO_COLOR only vetoes auto-detection; an explicit --color=always still wins
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let enabled = match when {
+        "always" => true,
+        "never" => false,
+        _ => !no_color && io::stdout().is_terminal(),
+    };
+
+    if enabled {
+        parse_grep_colors(&env::var("GREP_COLORS").unwrap_or_default())
+    } else {
+        Colors::default()
+    }
+}
+
+// Parse a GNU-style "ms=01;31:fn=35" spec on top of GNU grep's default palette
+fn parse_grep_colors(spec: &str) -> Colors {
+    let mut colors = Colors {
+        matched: Colour::Red.bold(),
+        filename: Colour::Purple.normal(),
+        line_num: Colour::Green.normal(),
+        byte_offset: Colour::Green.normal(),
+        separator: Colour::Cyan.normal(),
     };
 
+    for (key, val) in spec.split(':').filter_map(|cap| cap.split_once('=')) {
+        let style = parse_sgr(val);
+        match key {
+            "mt" | "ms" => colors.matched = style,
+            "fn" => colors.filename = style,
+            "ln" => colors.line_num = style,
+            "bn" => colors.byte_offset = style,
+            "se" => colors.separator = style,
+            _ => {}
+        }
+    }
+
+    colors
+}
+
+// Translate SGR parameters such as "01;31" or "38;5;208" into a Style
+fn parse_sgr(sgr: &str) -> Style {
+    const BASIC: [Colour; 8] = [
+        Colour::Black,
+        Colour::Red,
+        Colour::Green,
+        Colour::Yellow,
+        Colour::Blue,
+        Colour::Purple,
+        Colour::Cyan,
+        Colour::White,
+    ];
+    let mut style = Style::new();
+    let mut codes = sgr.split(';').filter_map(|c| c.parse::<u8>().ok());
+
+    while let Some(code) = codes.next() {
+        style = match code {
+            0 => Style::new(),
+            1 => style.bold(),
+            2 => style.dimmed(),
+            3 => style.italic(),
+            4 => style.underline(),
+            5 => style.blink(),
+            7 => style.reverse(),
+            8 => style.hidden(),
+            9 => style.strikethrough(),
+            30..=37 => style.fg(BASIC[(code - 30) as usize]),
+            40..=47 => style.on(BASIC[(code - 40) as usize]),
+            90..=97 => style.fg(Colour::Fixed(code - 90 + 8)),
+            100..=107 => style.on(Colour::Fixed(code - 100 + 8)),
+            38 | 48 => match (codes.next(), codes.next()) {
+                (Some(5), Some(n)) if code == 38 => style.fg(Colour::Fixed(n)),
+                (Some(5), Some(n)) => style.on(Colour::Fixed(n)),
+                _ => style,
+            },
+            _ => style,
+        };
+    }
+
+    style
+}
+
+// Write the line with each matched span painted, leaving the rest untouched
+fn highlight(
+    out: &mut impl Write,
+    text: &[u8],
+    spans: &[(usize, usize)],
+    style: Style,
+) -> io::Result<()> {
+    let mut last = 0;
+    for &(start, end) in spans.iter().filter(|(s, e)| s < e) {
+        out.write_all(&text[last..start])?;
+        write!(out, "{}", style.prefix())?;
+        out.write_all(&text[start..end])?;
+        write!(out, "{}", style.suffix())?;
+        last = end;
+    }
+    out.write_all(&text[last..])
+}
+
+fn parse_context(val: Option<&str>) -> MyResult<Option<usize>> {
+    val.map(|v| v.parse())
+        .transpose()
+        .map_err(|_| From::from(format!("{}: invalid context length argument", val.unwrap())))
+}
+
+pub fn run(config: Config) -> MyResult<i32> {
+    let start = Instant::now();
+    let entries = find_files(&config.files, &config.walk);
+    let find_files_elapsed = start.elapsed();
+    let show_fname = entries.len() > 1;
+    let mut any_failed = false;
+    let mut paths = vec![];
     for entry in entries {
         match entry {
-            Err(e) => eprintln!("{}", e),
-            Ok(filepath) => match open(&filepath) {
-                Err(e) => eprintln!("{}: {}", filepath, e),
-                Ok(file) => {
-                    let matches = find_lines(file, &config.pattern, config.invert_match)?;
-                    if config.count {
-                        print(&filepath, &format!("{}\n", matches.len()));
-                    } else {
-                        for line in &matches {
-                            print(&filepath, line);
+            Err(e) => {
+                eprintln!("{}", e);
+                any_failed = true;
+            }
+            Ok(filepath) => paths.push(filepath),
+        }
+    }
+    if config.sort_path {
+        paths.sort();
+    }
+    if config.in_place {
+        // a file named twice must only be edited once, or the edits would race
+        let mut seen = HashSet::new();
+        paths.retain(|path| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.into())));
+    }
+
+    let num_threads = config.threads.clamp(1, paths.len().max(1));
+    // JSON output marks where each file starts with its own events instead
+    let show_context = !config.only_matching
+        && !config.json
+        && (config.before_context > 0 || config.after_context > 0);
+    let next_path = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            let tx = tx.clone();
+            let (config, paths, next_path) = (&config, &paths, &next_path);
+            scope.spawn(move || {
+                // each worker claims the next unsearched path until none remain
+                loop {
+                    let index = next_path.fetch_add(1, Ordering::Relaxed);
+                    let Some(filepath) = paths.get(index) else {
+                        break;
+                    };
+                    let outcome = match &config.replace {
+                        Some(template) if config.in_place => {
+                            replace_file(config, filepath, template)
                         }
+                        _ => search_file(config, filepath, show_fname),
+                    };
+                    let outcome = outcome.unwrap_or_else(|e| {
+                        eprintln!("{}: {}", filepath, e);
+                        Outcome::failed()
+                    });
+                    // the receiver hangs up early once -q has seen a match
+                    if tx.send((index, outcome)).is_err() {
+                        break;
                     }
                 }
-            },
+            });
         }
-    }
+        drop(tx);
 
-    Ok(())
+        // buffer out-of-order results so whole files print in the order of `paths`
+        let mut stdout = io::stdout().lock();
+        let mut pending = HashMap::new();
+        let mut next_print = 0;
+        let mut printed_hunks = false;
+        let mut any_selected = false;
+        let (mut searches, mut searches_with_match) = (0, 0);
+        let mut totals = Stats::default();
+        for (index, outcome) in rx {
+            pending.insert(index, outcome);
+            while let Some(outcome) = pending.remove(&next_print) {
+                next_print += 1;
+                // a selected line ends a quiet search successfully, whatever else failed
+                if config.quiet && outcome.selected {
+                    return Ok(0);
+                }
+                any_selected |= outcome.selected;
+                any_failed |= outcome.failed;
+                if !outcome.failed {
+                    searches += 1;
+                    searches_with_match += usize::from(outcome.stats.matched_lines > 0);
+                }
+                totals.elapsed += outcome.stats.elapsed;
+                totals.match_elapsed += outcome.stats.match_elapsed;
+                totals.matched_lines += outcome.stats.matched_lines;
+                totals.matches += outcome.stats.matches;
+                totals.bytes_searched += outcome.stats.bytes_searched;
+
+                let output = outcome.output;
+                // hunks from different files are separated like non-adjacent hunks,
+                // while counts and file names run on without one
+                let hunks = outcome.hunks && !output.is_empty();
+                if show_context && printed_hunks && hunks {
+                    writeln!(stdout, "{}", config.colors.separator.paint("--"))?;
+                }
+                stdout.write_all(&output)?;
+                printed_hunks |= hunks;
+            }
+        }
+        if config.json {
+            let summary = json!({
+                "type": "summary",
+                "data": {
+                    "elapsed_total": json_duration(start.elapsed()),
+                    "stats": {
+                        "elapsed": json_duration(totals.elapsed),
+                        "searches": searches,
+                        "searches_with_match": searches_with_match,
+                        "bytes_searched": totals.bytes_searched,
+                        "matched_lines": totals.matched_lines,
+                        "matches": totals.matches,
+                    },
+                },
+            });
+            writeln!(stdout, "{}", summary)?;
+        } else if config.stats {
+            // searching time is summed across threads, so it can exceed the total
+            writeln!(stdout)?;
+            writeln!(stdout, "{} matches", totals.matches)?;
+            writeln!(stdout, "{} matched lines", totals.matched_lines)?;
+            writeln!(stdout, "{} files contained matches", searches_with_match)?;
+            writeln!(stdout, "{} files searched", searches)?;
+            writeln!(stdout, "{} bytes searched", totals.bytes_searched)?;
+            let seconds = |d: Duration| format!("{:.6} seconds", d.as_secs_f64());
+            writeln!(
+                stdout,
+                "{} spent finding files",
+                seconds(find_files_elapsed)
+            )?;
+            writeln!(stdout, "{} spent searching", seconds(totals.elapsed))?;
+            writeln!(stdout, "{} spent matching", seconds(totals.match_elapsed))?;
+            writeln!(stdout, "{}", seconds(start.elapsed()))?;
+        }
+        stdout.flush()?;
+
+        // POSIX grep: 0 if a line was selected, 1 if none were, 2 on any error
+        Ok(if any_failed {
+            2
+        } else if any_selected {
+            0
+        } else {
+            1
+        })
+    })
 }
 
-fn find_files(paths: &[String], recursive: bool) -> Vec<MyResult<String>> {
-    let mut results = vec![];
+impl Outcome {
+    fn failed() -> Outcome {
+        Outcome {
+            output: vec![],
+            selected: false,
+            failed: true,
+            hunks: false,
+            stats: Stats::default(),
+        }
+    }
+}
 
-    for path in paths {
-        match path.as_str() {
-            "-" => results.push(Ok(path.to_string())),
-            _ => match fs::metadata(path) {
-                Ok(metadata) => {
-                    if metadata.is_dir() {
-                        if recursive {
-                            for entry in WalkDir::new(path)
-                                .into_iter()
-                                .flatten()
-                                .filter(|e| e.file_type().is_file())
-                            {
-                                results.push(Ok(entry.path().display().to_string()));
-                            }
-                        } else {
-                            results.push(Err(From::from(format!("{} is a directory", path))));
-                        }
-                    } else if metadata.is_file() {
-                        results.push(Ok(path.to_string()))
+// Search a single file, returning everything it prints so output stays per-file atomic
+fn search_file(config: &Config, filepath: &str, show_fname: bool) -> MyResult<Outcome> {
+    let start = Instant::now();
+    let mut out = vec![];
+    let mut file = match open(filepath, config.search_zip) {
+        Err(e) => {
+            eprintln!("{}: {}", filepath, e);
+            return Ok(Outcome::failed());
+        }
+        Ok(file) => file,
+    };
+
+    // like GNU grep, a NUL byte in the first block marks the file as binary
+    // unless NUL is what separates the lines
+    let is_binary = config.binary_files != BinaryFiles::Text
+        && !config.null_data
+        && file.fill_buf()?.contains(&0);
+    let terminator = if config.null_data { b'\0' } else { b'\n' };
+    let skip = is_binary && config.binary_files == BinaryFiles::WithoutMatch;
+    // these modes only need to know whether the file matches at all
+    let lists_files = config.quiet || config.files_with_matches || config.files_without_match;
+    let prints_lines = !(lists_files || config.count || is_binary);
+
+    let colors = &config.colors;
+    let prefix = |sep: char, line_num: usize, offset: usize| {
+        let sep = colors.separator.paint(sep.to_string());
+        let mut prefix = String::new();
+        if show_fname {
+            prefix += &format!("{}{}", colors.filename.paint(filepath), sep);
+        }
+        if config.line_number {
+            prefix += &format!("{}{}", colors.line_num.paint(line_num.to_string()), sep);
+        }
+        if config.byte_offset {
+            prefix += &format!("{}{}", colors.byte_offset.paint(offset.to_string()), sep);
+        }
+        prefix
+    };
+    // only-matching output has no use for surrounding lines
+    let (before_context, after_context) = if prints_lines && !config.only_matching {
+        (config.before_context, config.after_context)
+    } else {
+        (0, 0)
+    };
+    let options = SearchOptions {
+        invert_match: config.invert_match,
+        before_context,
+        after_context,
+        max_count: if lists_files || (is_binary && !config.count) {
+            Some(1)
+        } else {
+            config.max_count
+        },
+        multiline: config.multiline,
+        terminator,
+        time_matching: config.stats,
+    };
+
+    let begin = json!({"type": "begin", "data": {"path": json_text(filepath.as_bytes())}});
+    let mut stats = if skip {
+        Stats::default()
+    } else {
+        find_lines(file, &config.matcher, &options, |line| {
+            if !prints_lines {
+                return Ok(());
+            }
+            // from here on the spans mark the replacements rather than the matches
+            let line = match (line, &config.replace) {
+                (Line::Match(record), Some(template)) => {
+                    // replace within the text the spans were found in, then restore the ending
+                    let content = record
+                        .text
+                        .strip_suffix(&[terminator])
+                        .unwrap_or(&record.text);
+                    let (mut text, spans) =
+                        config.matcher.replace(content, &record.spans, template);
+                    text.extend_from_slice(&record.text[content.len()..]);
+                    Line::Match(Record {
+                        text,
+                        spans,
+                        ..record
+                    })
+                }
+                (line, _) => line,
+            };
+            if config.json {
+                // JSON output only holds events, so it is empty until the first one
+                if out.is_empty() {
+                    writeln!(out, "{}", begin)?;
+                }
+                if let Some(event) = json_line(filepath, &line) {
+                    writeln!(out, "{}", event)?;
+                }
+                return Ok(());
+            }
+            match line {
+                Line::Match(record) if config.only_matching => {
+                    for &(start, end) in record.spans.iter().filter(|(s, e)| s < e) {
+                        write!(
+                            out,
+                            "{}",
+                            prefix(':', record.line_num, record.offset + start)
+                        )?;
+                        let text = &record.text[start..end];
+                        highlight(&mut out, text, &[(0, text.len())], colors.matched)?;
+                        out.write_all(&[terminator])?;
                     }
                 }
-                Err(e) => results.push(Err(From::from(format!("{}: {}", path, e)))),
-            },
+                Line::Match(record) => {
+                    write!(out, "{}", prefix(':', record.line_num, record.offset))?;
+                    highlight(&mut out, &record.text, &record.spans, colors.matched)?;
+                }
+                Line::Context(record) => {
+                    write!(out, "{}", prefix('-', record.line_num, record.offset))?;
+                    out.write_all(&record.text)?;
+                }
+                Line::Break => writeln!(out, "{}", colors.separator.paint("--"))?,
+            }
+            Ok(())
+        })?
+    };
+
+    stats.elapsed = start.elapsed();
+    let num_matches = stats.matched_lines;
+
+    let selected = if config.files_without_match {
+        num_matches == 0
+    } else {
+        num_matches > 0
+    };
+    if config.quiet {
+        // nothing is printed, only the exit status matters
+    } else if config.json {
+        // a binary file prints none of its lines, but its match still gets events
+        if out.is_empty() && num_matches > 0 {
+            writeln!(out, "{}", begin)?;
+        }
+        if !out.is_empty() {
+            let end = json!({
+                "type": "end",
+                "data": {
+                    "path": json_text(filepath.as_bytes()),
+                    "stats": {
+                        "elapsed": json_duration(stats.elapsed),
+                        "bytes_searched": stats.bytes_searched,
+                        "matched_lines": stats.matched_lines,
+                        "matches": stats.matches,
+                    },
+                },
+            });
+            writeln!(out, "{}", end)?;
         }
+    } else if config.files_with_matches || config.files_without_match {
+        if selected {
+            writeln!(out, "{}", colors.filename.paint(filepath))?;
+        }
+    } else if config.count {
+        if show_fname {
+            write!(
+                out,
+                "{}{}",
+                colors.filename.paint(filepath),
+                colors.separator.paint(":")
+            )?;
+        }
+        writeln!(out, "{}", num_matches)?;
+    } else if is_binary && selected {
+        let name = if filepath == "-" {
+            "(standard input)"
+        } else {
+            filepath
+        };
+        writeln!(out, "Binary file {} matches", name)?;
     }
 
-    results
+    Ok(Outcome {
+        output: out,
+        selected,
+        failed: false,
+        hunks: prints_lines,
+        stats,
+    })
 }
 
-fn find_lines<T: BufRead>(
-    mut file: T, // Trait bound, type must implement BufRead trait. Same as `impl BufRead`.
-    pattern: &Regex,
-    invert_match: bool,
-) -> MyResult<Vec<String>> {
-    let mut results = vec![];
-    let mut buffer = String::new();
+// Rewrite a file with every match replaced, or with --dry-run return the changes as a diff
+fn replace_file(config: &Config, filepath: &str, template: &[u8]) -> MyResult<Outcome> {
+    let start = Instant::now();
+    if filepath == "-" {
+        return Err(From::from("cannot edit standard input in place"));
+    }
+    let contents = fs::read(filepath)?;
+    let mut stats = Stats {
+        bytes_searched: contents.len(),
+        ..Default::default()
+    };
+    let mut out = vec![];
 
-    // preserve line endings so loop until EOF reached
-    loop {
-        let bytes = file.read_line(&mut buffer)?;
-        if bytes == 0 {
-            break;
+    // binary files are left alone unless they are to be treated as text
+    let is_binary = contents[..contents.len().min(8 * 1024)].contains(&0);
+    if is_binary && config.binary_files != BinaryFiles::Text {
+        return Ok(Outcome {
+            output: out,
+            selected: false,
+            failed: false,
+            hunks: false,
+            stats,
+        });
+    }
+
+    let mut replaced = Vec::with_capacity(contents.len());
+    let mut changes = vec![];
+    for (index, line) in contents.split_inclusive(|&b| b == b'\n').enumerate() {
+        let done = config
+            .max_count
+            .is_some_and(|max| stats.matched_lines >= max);
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let spans = if done {
+            vec![]
+        } else {
+            let start = Instant::now();
+            let spans = config.matcher.find_iter(content);
+            stats.match_elapsed += start.elapsed();
+            spans
+        };
+        if spans.is_empty() {
+            replaced.extend_from_slice(line);
+            continue;
         }
 
-        if pattern.is_match(&buffer) ^ invert_match {
-            // BitXor bit-wise exclusive OR operation
-            results.push(mem::take(&mut buffer)) // take ownership of the buffer instead of cloning
+        stats.matched_lines += 1;
+        stats.matches += spans.len();
+        let (mut text, _) = config.matcher.replace(content, &spans, template);
+        text.extend_from_slice(&line[content.len()..]);
+        replaced.extend_from_slice(&text);
+        if text != line {
+            changes.push(Change {
+                line_num: index + 1,
+                old: line,
+                new: text,
+            });
         }
+    }
 
-        buffer.clear();
+    if config.dry_run {
+        write_diff(&mut out, filepath, &changes)?;
+    } else if !changes.is_empty() {
+        write_atomically(filepath, &replaced)?;
     }
-    Ok(results)
+    stats.elapsed = start.elapsed();
+
+    Ok(Outcome {
+        output: out,
+        selected: stats.matched_lines > 0,
+        failed: false,
+        hunks: false,
+        stats,
+    })
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+// Swap in the new contents by renaming a sibling temp file over the original
+fn write_atomically(filepath: &str, contents: &[u8]) -> MyResult<()> {
+    let path = Path::new(filepath);
+    let name = path.file_name().ok_or("not a file")?.to_string_lossy();
+    // the counter keeps temp names apart when this process writes several at once
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let temp = path.with_file_name(format!(
+        ".{}.grepr-{}-{}.tmp",
+        name,
+        process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    // create_new refuses to reuse a file that is already there, which is not ours to remove
+    let mut file = File::options().write(true).create_new(true).open(&temp)?;
+    let result = (|| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        // never leave a half-written temp file behind
+        let _ = fs::remove_file(&temp);
     }
+    Ok(result?)
 }
 
-#[cfg(test)]
-mod unit_tests {
-    use super::{find_files, find_lines};
-    use rand::{distributions::Alphanumeric, Rng};
-    use regex::{Regex, RegexBuilder};
-    use std::io::Cursor;
+// Print changed lines as a unified diff without context, like `diff -U0`
+fn write_diff(out: &mut impl Write, filepath: &str, changes: &[Change]) -> io::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "--- {}", filepath)?;
+    writeln!(out, "+++ {}", filepath)?;
 
-    #[test]
+    // a replacement containing newlines shifts every later line
+    let mut shift = 0;
+    for hunk in changes.chunk_by(|a, b| b.line_num == a.line_num + 1) {
+        let old_start = hunk[0].line_num;
+        let old_count = hunk.len();
+        let new_count: usize = hunk
+            .iter()
+            .map(|c| c.new.split_inclusive(|&b| b == b'\n').count())
+            .sum();
+        let new_start = old_start.checked_add_signed(shift).unwrap_or(0);
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            diff_range(old_start, old_count),
+            diff_range(new_start, new_count)
+        )?;
+        for change in hunk {
+            write_diff_lines(out, '-', change.old)?;
+        }
+        for change in hunk {
+            write_diff_lines(out, '+', &change.new)?;
+        }
+        shift += new_count as isize - old_count as isize;
+    }
+    Ok(())
+}
+
+// An empty range is given by the line before it, a single line by its number alone
+fn diff_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start - 1),
+        1 => start.to_string(),
+        _ => format!("{},{}", start, count),
+    }
+}
+
+fn write_diff_lines(out: &mut impl Write, sign: char, text: &[u8]) -> io::Result<()> {
+    for line in text.split_inclusive(|&b| b == b'\n') {
+        write!(out, "{}", sign)?;
+        out.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writeln!(out, "\n\\ No newline at end of file")?;
+        }
+    }
+    Ok(())
+}
+
+// A match or context event in the style of ripgrep's JSON output
+fn json_line(filepath: &str, line: &Line) -> Option<Value> {
+    let (kind, record) = match line {
+        Line::Match(record) => ("match", record),
+        Line::Context(record) => ("context", record),
+        Line::Break => return None,
+    };
+    let submatches: Vec<_> = record
+        .spans
+        .iter()
+        .map(|&(start, end)| {
+            json!({
+                "match": json_text(&record.text[start..end]),
+                "start": start,
+                "end": end,
+            })
+        })
+        .collect();
+
+    Some(json!({
+        "type": kind,
+        "data": {
+            "path": json_text(filepath.as_bytes()),
+            "lines": json_text(&record.text),
+            "line_number": record.line_num,
+            "absolute_offset": record.offset,
+            "submatches": submatches,
+        },
+    }))
+}
+
+fn json_text(bytes: &[u8]) -> Value {
+    json!({ "text": String::from_utf8_lossy(bytes) })
+}
+
+fn json_duration(duration: Duration) -> Value {
+    json!({
+        "secs": duration.as_secs(),
+        "nanos": duration.subsec_nanos(),
+        "human": format!("{:.6}s", duration.as_secs_f64()),
+    })
+}
+
+fn find_files(paths: &[String], walk: &WalkOptions) -> Vec<MyResult<String>> {
+    let mut results = vec![];
+
+    for path in paths {
+        match path.as_str() {
+            "-" => results.push(Ok(path.to_string())),
+            _ => match fs::metadata(path) {
+                Ok(metadata) => {
+                    if metadata.is_dir() {
+                        if walk.recursive {
+                            // hidden and ignored entries are pruned before descending into them
+                            let walker = WalkBuilder::new(path)
+                                .standard_filters(!walk.no_ignore)
+                                .hidden(!walk.hidden)
+                                .max_depth(walk.max_depth)
+                                .build();
+                            for entry in walker
+                                .flatten()
+                                .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+                            {
+                                let name = entry.file_name();
+                                if (walk.include.is_empty() || walk.include.is_match(name))
+                                    && !walk.exclude.is_match(name)
+                                {
+                                    results.push(Ok(entry.path().display().to_string()));
+                                }
+                            }
+                        } else {
+                            results.push(Err(From::from(format!("{} is a directory", path))));
+                        }
+                    } else if metadata.is_file() {
+                        results.push(Ok(path.to_string()))
+                    }
+                }
+                Err(e) => results.push(Err(From::from(format!("{}: {}", path, e)))),
+            },
+        }
+    }
+
+    results
+}
+
+// Stream each selected line (and its context) to `emit`, returning what was counted on the way
+fn find_lines<T: BufRead>(
+    mut file: T, // Trait bound, type must implement BufRead trait. Same as `impl BufRead`.
+    matcher: &Matcher,
+    options: &SearchOptions,
+    mut emit: impl FnMut(Line) -> MyResult<()>,
+) -> MyResult<Stats> {
+    let before_context = options.before_context;
+    let mut num_matches = 0;
+    let mut stats = Stats::default();
+    let mut find_iter = |text: &[u8]| {
+        if !options.time_matching {
+            return matcher.find_iter(text);
+        }
+        let start = Instant::now();
+        let spans = matcher.find_iter(text);
+        stats.match_elapsed += start.elapsed();
+        spans
+    };
+    let mut buffer = vec![];
+    let mut before = VecDeque::with_capacity(before_context);
+    let mut after_remaining = 0;
+    let mut last_emitted: Option<usize> = None;
+    let show_breaks = before_context > 0 || options.after_context > 0;
+    let mut offset = 0;
+    let terminator = options.terminator;
+
+    // a multiline search matches the whole input up front and then walks it line by line
+    let mut contents = vec![];
+    let mut all_spans = vec![];
+    let mut next_span = 0;
+    if options.multiline {
+        file.read_to_end(&mut contents)?;
+        all_spans = find_iter(&contents);
+    }
+
+    // preserve line endings so loop until EOF reached
+    for line_num in 1.. {
+        // once max-count is reached only the trailing context is still needed
+        let done = options.max_count.is_some_and(|max| num_matches >= max);
+        if done && after_remaining == 0 {
+            break;
+        }
+        let bytes = if options.multiline {
+            let rest = &contents[offset..];
+            let len = rest
+                .iter()
+                .position(|&b| b == terminator)
+                .map_or(rest.len(), |i| i + 1);
+            buffer.extend_from_slice(&rest[..len]);
+            len
+        } else {
+            file.read_until(terminator, &mut buffer)?
+        };
+        if bytes == 0 {
+            break;
+        }
+
+        // match against the line without its terminator so spans never include it
+        let content = buffer.strip_suffix(&[terminator]).unwrap_or(&buffer);
+        let spans = if options.multiline {
+            // drop the matches that ended before this line, then take those touching it
+            while all_spans
+                .get(next_span)
+                .is_some_and(|&(start, end)| start < offset && end <= offset)
+            {
+                next_span += 1;
+            }
+            let clip = |pos: usize| pos.clamp(offset, offset + content.len()) - offset;
+            all_spans[next_span..]
+                .iter()
+                .take_while(|&&(start, _)| start < offset + bytes)
+                .map(|&(start, end)| (clip(start), clip(end)))
+                .collect()
+        } else {
+            find_iter(content)
+        };
+        let line_offset = offset;
+        offset += bytes;
+        // only a line that is kept takes the buffer, so the rest reuse its allocation
+        let mut take_record = |spans| Record {
+            line_num,
+            offset: line_offset,
+            text: mem::take(&mut buffer), // take ownership of the buffer instead of cloning
+            spans,
+        };
+
+        if !done && (!spans.is_empty() ^ options.invert_match) {
+            // BitXor bit-wise exclusive OR operation
+            let first = line_num - before.len();
+            if show_breaks && matches!(last_emitted, Some(last) if first > last + 1) {
+                emit(Line::Break)?;
+            }
+            for record in before.drain(..) {
+                emit(Line::Context(record))?;
+            }
+            stats.matches += spans.len();
+            emit(Line::Match(take_record(spans)))?;
+            num_matches += 1;
+            after_remaining = options.after_context;
+            last_emitted = Some(line_num);
+        } else if after_remaining > 0 {
+            emit(Line::Context(take_record(vec![])))?;
+            after_remaining -= 1;
+            last_emitted = Some(line_num);
+        } else if before_context > 0 {
+            // keep a sliding window of lines that may precede the next match
+            if before.len() == before_context {
+                before.pop_front();
+            }
+            before.push_back(take_record(vec![]));
+        }
+        buffer.clear();
+    }
+
+    stats.matched_lines = num_matches;
+    stats.bytes_searched = if options.multiline {
+        contents.len()
+    } else {
+        offset
+    };
+    Ok(stats)
+}
+
+fn build_matcher(patterns: &[String], options: &MatcherOptions) -> MyResult<Matcher> {
+    let &MatcherOptions {
+        fixed_strings,
+        insensitive,
+        word_regexp,
+        line_regexp,
+        multiline,
+        perl,
+    } = options;
+    if perl && cfg!(not(feature = "pcre")) {
+        return Err(From::from(
+            "-P is not available, rebuild grepr with `--features pcre`",
+        ));
+    }
+
+    // Aho-Corasick only folds ASCII case, so leave anything else to the regex engine,
+    // which is also needed to anchor -x to the lines within a multiline buffer
+    let literal = fixed_strings
+        && !(insensitive && patterns.iter().any(|p| !p.is_ascii()))
+        && !(multiline && line_regexp);
+
+    let engine = if literal {
+        Engine::Literal(Box::new(
+            AhoCorasickBuilder::new()
+                .ascii_case_insensitive(insensitive)
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(patterns),
+        ))
+    } else if patterns.is_empty() {
+        // no patterns at all can never match
+        Engine::Literal(Box::new(AhoCorasick::new_auto_configured::<&str>(&[])))
+    } else {
+        let mut alternatives = vec![];
+        for pattern in patterns {
+            let pattern = if fixed_strings {
+                regex::escape(pattern)
+            } else {
+                pattern.to_string()
+            };
+            // check each pattern alone so the error names the one at fault
+            check_pattern(&pattern, perl)?;
+            alternatives.push(format!("(?:{})", pattern));
+        }
+        let combined = alternatives.join("|");
+        let combined = if line_regexp {
+            format!("^(?:{})$", combined)
+        } else {
+            combined
+        };
+        match perl {
+            #[cfg(feature = "pcre")]
+            true => {
+                let mut flags = String::new();
+                if insensitive {
+                    flags.push('i');
+                }
+                if multiline {
+                    flags.push('m');
+                }
+                let combined = if flags.is_empty() {
+                    combined
+                } else {
+                    format!("(?{}){}", flags, combined)
+                };
+                Engine::Fancy(fancy_regex::Regex::new(&combined)?)
+            }
+            _ => Engine::Regex(
+                RegexBuilder::new(&combined)
+                    .case_insensitive(insensitive)
+                    .multi_line(multiline)
+                    .build()?,
+            ),
+        }
+    };
+
+    Ok(Matcher {
+        engine,
+        word_regexp,
+        line_regexp,
+        multiline,
+    })
+}
+
+fn check_pattern(pattern: &str, perl: bool) -> MyResult<()> {
+    #[cfg(feature = "pcre")]
+    if perl {
+        fancy_regex::Regex::new(pattern).map_err(|_| format!("Invalid pattern \"{}\"", pattern))?;
+        return Ok(());
+    }
+
+    match RegexBuilder::new(pattern).build() {
+        Ok(_) => Ok(()),
+        // point at -P when only the backtracking engine could run the pattern
+        Err(regex::Error::Syntax(msg))
+            if !perl && (msg.contains("look-around") || msg.contains("backreferences")) =>
+        {
+            Err(From::from(format!(
+                "Invalid pattern \"{}\": look-around and backreferences need -P",
+                pattern
+            )))
+        }
+        Err(_) => Err(From::from(format!("Invalid pattern \"{}\"", pattern))),
+    }
+}
+
+impl Matcher {
+    // Byte ranges of every accepted match within `text`
+    fn find_iter(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        let mut spans = vec![];
+        let mut pos = 0;
+        while pos <= text.len() {
+            let Some((start, end)) = self.find_at(text, pos) else {
+                break;
+            };
+            if self.word_regexp && !is_word_bounded(text, start, end) {
+                // retry just past the rejected start in case a later match is a word
+                pos = start + 1;
+                continue;
+            }
+            if self.line_regexp && !self.multiline && (start, end) != (0, text.len()) {
+                break;
+            }
+            spans.push((start, end));
+            pos = if end > start { end } else { end + 1 };
+        }
+        spans
+    }
+
+    // Substitute the expanded template for each span, returning the new text and
+    // the ranges the substitutions now occupy. `text` must be exactly what the spans
+    // were found in, without the line ending, so anchors and groups match again
+    fn replace(
+        &self,
+        text: &[u8],
+        spans: &[(usize, usize)],
+        template: &[u8],
+    ) -> (Vec<u8>, Vec<(usize, usize)>) {
+        let mut replaced = Vec::with_capacity(text.len());
+        let mut new_spans = vec![];
+        let mut last = 0;
+        for &(start, end) in spans {
+            replaced.extend_from_slice(&text[last..start]);
+            let new_start = replaced.len();
+            match &self.engine {
+                Engine::Regex(re) => {
+                    let mut locs = re.capture_locations();
+                    re.captures_read_at(&mut locs, text, start);
+                    let group = |name: &str| {
+                        let index = name
+                            .parse()
+                            .ok()
+                            .or_else(|| re.capture_names().position(|n| n == Some(name)))?;
+                        locs.get(index).map(|(s, e)| &text[s..e])
+                    };
+                    expand_template(template, group, &mut replaced);
+                }
+                // literal patterns have no groups beyond the whole match
+                Engine::Literal(_) => {
+                    let group = |name: &str| (name == "0").then(|| &text[start..end]);
+                    expand_template(template, group, &mut replaced);
+                }
+                #[cfg(feature = "pcre")]
+                Engine::Fancy(re) => {
+                    let captures = utf8_runs(text)
+                        .find(|&(run_start, run)| {
+                            (run_start..=run_start + run.len()).contains(&start)
+                        })
+                        .and_then(|(run_start, run)| {
+                            re.captures_from_pos(run, start - run_start).ok().flatten()
+                        });
+                    let group = |name: &str| {
+                        let captures = captures.as_ref()?;
+                        let m = match name.parse() {
+                            Ok(index) => captures.get(index),
+                            Err(_) => captures.name(name),
+                        }?;
+                        Some(m.as_str().as_bytes())
+                    };
+                    expand_template(template, group, &mut replaced);
+                }
+            }
+            new_spans.push((new_start, replaced.len()));
+            last = end;
+        }
+        replaced.extend_from_slice(&text[last..]);
+        (replaced, new_spans)
+    }
+
+    fn find_at(&self, text: &[u8], pos: usize) -> Option<(usize, usize)> {
+        match &self.engine {
+            Engine::Regex(re) => re.find_at(text, pos).map(|m| (m.start(), m.end())),
+            Engine::Literal(ac) => ac
+                .find(&text[pos..])
+                .map(|m| (pos + m.start(), pos + m.end())),
+            #[cfg(feature = "pcre")]
+            Engine::Fancy(re) => utf8_runs(text).find_map(|(run_start, run)| {
+                if pos > run_start + run.len() {
+                    return None;
+                }
+                let mut from = pos.saturating_sub(run_start);
+                while !run.is_char_boundary(from) {
+                    from += 1;
+                }
+                // a search that exceeds the backtracking limit counts as no match
+                let m = re.find_from_pos(run, from).ok()??;
+                Some((run_start + m.start(), run_start + m.end()))
+            }),
+        }
+    }
+}
+
+// fancy-regex only searches str, so each run of valid UTF-8 is searched on its own
+// with the offset where it starts, and invalid bytes can never be part of a match
+#[cfg(feature = "pcre")]
+fn utf8_runs(text: &[u8]) -> impl Iterator<Item = (usize, &str)> {
+    text.utf8_chunks().scan(0, |start, chunk| {
+        let run = (*start, chunk.valid());
+        *start += chunk.valid().len() + chunk.invalid().len();
+        Some(run)
+    })
+}
+
+// Expand $1, ${1}, $name and ${name} like regex's Captures::expand, with $$ for a
+// literal dollar sign and unknown groups expanding to nothing
+fn expand_template<'t>(
+    template: &[u8],
+    group: impl Fn(&str) -> Option<&'t [u8]>,
+    dst: &mut Vec<u8>,
+) {
+    let is_name = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+    let mut rest = template;
+    while let Some(pos) = rest.iter().position(|&b| b == b'$') {
+        dst.extend_from_slice(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let (name, len) = match rest.first() {
+            Some(b'$') => {
+                dst.push(b'$');
+                rest = &rest[1..];
+                continue;
+            }
+            Some(b'{') => match rest.iter().position(|&b| b == b'}') {
+                Some(close) => (&rest[1..close], close + 1),
+                None => (&rest[..0], 0),
+            },
+            _ => {
+                let len = rest.iter().take_while(|b| is_name(b)).count();
+                (&rest[..len], len)
+            }
+        };
+        match std::str::from_utf8(name) {
+            Ok(name) if !name.is_empty() => {
+                dst.extend_from_slice(group(name).unwrap_or_default());
+                rest = &rest[len..];
+            }
+            // a dollar sign that starts no group is kept as is
+            _ => dst.push(b'$'),
+        }
+    }
+    dst.extend_from_slice(rest);
+}
+
+// A match is a whole word when no word character touches either end
+fn is_word_bounded(text: &[u8], start: usize, end: usize) -> bool {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let before = (start.saturating_sub(4)..start)
+        .find_map(|i| std::str::from_utf8(&text[i..start]).ok())
+        .and_then(|s| s.chars().next_back());
+    let after = (end + 1..=text.len().min(end + 4))
+        .find_map(|i| std::str::from_utf8(&text[end..i]).ok())
+        .and_then(|s| s.chars().next());
+    !is_word(before) && !is_word(after)
+}
+
+fn open(filename: &str, search_zip: bool) -> MyResult<Box<dyn BufRead>> {
+    let mut file: Box<dyn BufRead> = match filename {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(File::open(filename)?)),
+    };
+    if !search_zip {
+        return Ok(file);
+    }
+
+    // peeking with fill_buf leaves the header in place for the decoder
+    Ok(match Compression::detect(filename, file.fill_buf()?) {
+        Some(Compression::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Some(Compression::Bzip2) => Box::new(BufReader::new(MultiBzDecoder::new(file))),
+        Some(Compression::Xz) => Box::new(BufReader::new(XzDecoder::new_multi_decoder(file))),
+        Some(Compression::Zstd) => Box::new(BufReader::new(ZstdDecoder::with_buffer(file)?)),
+        None => file,
+    })
+}
+
+impl Compression {
+    // Magic bytes are trusted first, falling back to the file extension
+    fn detect(filename: &str, header: &[u8]) -> Option<Compression> {
+        const MAGIC: [(&[u8], Compression); 4] = [
+            (b"\x1f\x8b", Compression::Gzip),
+            (b"BZh", Compression::Bzip2),
+            (b"\xfd7zXZ\x00", Compression::Xz),
+            (b"\x28\xb5\x2f\xfd", Compression::Zstd),
+        ];
+        const EXTENSIONS: [(&str, Compression); 4] = [
+            (".gz", Compression::Gzip),
+            (".bz2", Compression::Bzip2),
+            (".xz", Compression::Xz),
+            (".zst", Compression::Zstd),
+        ];
+
+        let by_magic = MAGIC
+            .into_iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, format)| format);
+        by_magic.or_else(|| {
+            EXTENSIONS
+                .into_iter()
+                .find(|(ext, _)| filename.ends_with(ext))
+                .map(|(_, format)| format)
+        })
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::{
+        build_matcher, check_pattern, expand_template, find_files, find_lines, highlight,
+        is_word_bounded, json_line, parse_grep_colors, parse_sgr, Colors, Compression, Line,
+        Matcher, MatcherOptions, Record, SearchOptions, WalkOptions,
+    };
+    use ansi_term::{Colour, Style};
+    use rand::{distributions::Alphanumeric, Rng};
+    use std::{fs, io::Cursor, path::Path};
+
+    #[test]
     fn test_find_files() {
         // Verify that the function finds a file known to exist
-        let files = find_files(&["./tests/inputs/fox.txt".to_string()], false);
+        let files = find_files(
+            &["./tests/inputs/fox.txt".to_string()],
+            &WalkOptions::default(),
+        );
         assert_eq!(files.len(), 1);
         assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
 
         // The function should reject a directory without the recursive option
-        let files = find_files(&["./tests/inputs".to_string()], false);
+        let files = find_files(&["./tests/inputs".to_string()], &WalkOptions::default());
         assert_eq!(files.len(), 1);
         if let Err(e) = &files[0] {
             assert_eq!(e.to_string(), "./tests/inputs is a directory");
         }
 
         // Verify the function recurses to find four files in the directory
-        let res = find_files(&["./tests/inputs".to_string()], true);
+        let res = find_files(
+            &["./tests/inputs".to_string()],
+            &WalkOptions {
+                recursive: true,
+                ..Default::default()
+            },
+        );
         let mut files: Vec<String> = res
             .iter()
             .map(|r| r.as_ref().unwrap().replace("\\", "/"))
@@ -226,40 +1835,722 @@ mod unit_tests {
             .collect();
 
         // Verify that the function returns the bad file as an error
-        let files = find_files(&[bad], false);
+        let files = find_files(&[bad], &WalkOptions::default());
         assert_eq!(files.len(), 1);
         assert!(files[0].is_err());
     }
 
+    #[test]
+    fn test_find_files_filters() {
+        // Build a small git-like tree with ignore files and hidden entries
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(7)
+            .map(char::from)
+            .collect();
+        let root = std::env::temp_dir().join(format!("grepr-walk-{}", suffix));
+        for dir in [".git", "build", "sub/deep"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for (file, contents) in [
+            (".gitignore", "*.log\nbuild/\n"),
+            (".ignore", "secret.txt\n"),
+            (".hidden.txt", ""),
+            ("a.txt", ""),
+            ("b.md", ""),
+            ("app.log", ""),
+            ("secret.txt", ""),
+            ("build/out.txt", ""),
+            ("sub/c.txt", ""),
+            ("sub/deep/d.txt", ""),
+        ] {
+            fs::write(root.join(file), contents).unwrap();
+        }
+
+        let find = |walk: WalkOptions| -> Vec<String> {
+            let mut files: Vec<String> = find_files(&[root.display().to_string()], &walk)
+                .iter()
+                .map(|r| {
+                    let path = r.as_ref().unwrap();
+                    let rel = Path::new(path).strip_prefix(&root).unwrap();
+                    rel.display().to_string().replace("\\", "/")
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let recursive = || WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let globs = |glob: &str| {
+            globset::GlobSetBuilder::new()
+                .add(globset::Glob::new(glob).unwrap())
+                .build()
+                .unwrap()
+        };
+
+        // Ignored and hidden files are skipped by default
+        assert_eq!(
+            find(recursive()),
+            ["a.txt", "b.md", "sub/c.txt", "sub/deep/d.txt"]
+        );
+
+        // Hidden files are searched on request but ignore rules still apply
+        assert_eq!(
+            find(WalkOptions {
+                hidden: true,
+                ..recursive()
+            }),
+            [
+                ".gitignore",
+                ".hidden.txt",
+                ".ignore",
+                "a.txt",
+                "b.md",
+                "sub/c.txt",
+                "sub/deep/d.txt"
+            ]
+        );
+
+        // Ignore files can be disregarded entirely
+        assert_eq!(
+            find(WalkOptions {
+                no_ignore: true,
+                ..recursive()
+            }),
+            [
+                "a.txt",
+                "app.log",
+                "b.md",
+                "build/out.txt",
+                "secret.txt",
+                "sub/c.txt",
+                "sub/deep/d.txt"
+            ]
+        );
+
+        // Include and exclude globs match against the file name
+        assert_eq!(
+            find(WalkOptions {
+                include: globs("*.txt"),
+                exclude: globs("c.*"),
+                ..recursive()
+            }),
+            ["a.txt", "sub/deep/d.txt"]
+        );
+
+        // Depth is counted from the directory given on the command line
+        assert_eq!(
+            find(WalkOptions {
+                max_depth: Some(1),
+                ..recursive()
+            }),
+            ["a.txt", "b.md"]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn test_find_lines() {
         let text = b"Lorem\nIpsum\r\nDOLOR";
 
         // The pattern _or_ should match the one line, "Lorem"
-        let re1 = Regex::new("or").unwrap();
-        let matches = find_lines(Cursor::new(&text), &re1, false);
+        let re1 = build_matcher(&["or".to_string()], &MatcherOptions::default()).unwrap();
+        let matches = find_lines(Cursor::new(&text), &re1, &SearchOptions::default(), |_| {
+            Ok(())
+        });
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 1);
+        assert_eq!(matches.unwrap().matched_lines, 1);
 
         // When inverted, the function should match the other two lines
-        let matches = find_lines(Cursor::new(&text), &re1, true);
+        let matches = find_lines(
+            Cursor::new(&text),
+            &re1,
+            &SearchOptions {
+                invert_match: true,
+                ..Default::default()
+            },
+            |_| Ok(()),
+        );
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
+        assert_eq!(matches.unwrap().matched_lines, 2);
 
         // This regex will be case-insensitive
-        let re2 = RegexBuilder::new("or")
-            .case_insensitive(true)
-            .build()
-            .unwrap();
+        let re2 = build_matcher(
+            &["or".to_string()],
+            &MatcherOptions {
+                insensitive: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
 
         // The two lines "Lorem" and "DOLOR" should match
-        let matches = find_lines(Cursor::new(&text), &re2, false);
+        let matches = find_lines(Cursor::new(&text), &re2, &SearchOptions::default(), |_| {
+            Ok(())
+        });
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
+        assert_eq!(matches.unwrap().matched_lines, 2);
 
         // When inverted, the remaining line should match
-        let matches = find_lines(Cursor::new(&text), &re2, true);
+        let matches = find_lines(
+            Cursor::new(&text),
+            &re2,
+            &SearchOptions {
+                invert_match: true,
+                ..Default::default()
+            },
+            |_| Ok(()),
+        );
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 1);
+        assert_eq!(matches.unwrap().matched_lines, 1);
+    }
+
+    // Run find_lines over `text`, gathering every line it emits
+    fn collect(text: &[u8], matcher: &Matcher, options: SearchOptions) -> Vec<Line> {
+        let mut lines = vec![];
+        find_lines(Cursor::new(text), matcher, &options, |line| {
+            lines.push(line);
+            Ok(())
+        })
+        .unwrap();
+        lines
+    }
+
+    // Summarize each line as its separator and line number, e.g. ":2" or "-3"
+    fn describe(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| match line {
+                Line::Match(r) => format!(":{}", r.line_num),
+                Line::Context(r) => format!("-{}", r.line_num),
+                Line::Break => "--".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_lines_context() {
+        let text = b"a\nfoo\nb\nc\nd\ne\nfoo\nf\nfoo\n";
+        let re = build_matcher(&["foo".to_string()], &MatcherOptions::default()).unwrap();
+
+        // Trailing context stops at the next match and resumes after it
+        let lines = collect(
+            &text[..],
+            &re,
+            SearchOptions {
+                after_context: 1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(describe(&lines), [":2", "-3", "--", ":7", "-8", ":9"]);
+
+        // Leading context never repeats lines that were already emitted
+        let lines = collect(
+            &text[..],
+            &re,
+            SearchOptions {
+                before_context: 2,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            describe(&lines),
+            ["-1", ":2", "--", "-5", "-6", ":7", "-8", ":9"]
+        );
+
+        // Overlapping windows merge into a single hunk
+        let lines = collect(
+            &text[..],
+            &re,
+            SearchOptions {
+                before_context: 2,
+                after_context: 2,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            describe(&lines),
+            ["-1", ":2", "-3", "-4", "-5", "-6", ":7", "-8", ":9"]
+        );
+    }
+
+    #[test]
+    fn test_find_lines_multiline() {
+        let text = b"fn foo(\n    bar,\n) {}\nfoo(bar)\n";
+        let re = build_matcher(
+            &[r"foo\(\n\s+bar".to_string()],
+            &MatcherOptions {
+                multiline: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Each line a match touches is reported with the part of the match it holds
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        let lines = collect(&text[..], &re, options);
+        assert_eq!(describe(&lines), [":1", ":2"]);
+        let spans: Vec<_> = lines
+            .iter()
+            .map(|line| match line {
+                Line::Match(r) => r.spans.clone(),
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(spans, [vec![(3, 7)], vec![(0, 7)]]);
+
+        // Without multiline the newline can never be matched
+        assert!(collect(&text[..], &re, SearchOptions::default()).is_empty());
+
+        // With multiline, -x anchors to each line rather than the whole input
+        let re = build_matcher(
+            &["foo(bar)".to_string()],
+            &MatcherOptions {
+                fixed_strings: true,
+                line_regexp: true,
+                multiline: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        assert_eq!(describe(&collect(&text[..], &re, options)), [":4"]);
+
+        // NUL-terminated records may hold newlines
+        let re = build_matcher(&["b".to_string()], &MatcherOptions::default()).unwrap();
+        let options = SearchOptions {
+            terminator: b'\0',
+            ..Default::default()
+        };
+        let lines = collect(b"a\nb\0c\0b", &re, options);
+        assert_eq!(describe(&lines), [":1", ":3"]);
+    }
+
+    #[test]
+    fn test_find_lines_stats() {
+        let text = b"foo foo\nbar\nfoo\nbaz\n";
+        let re = build_matcher(&["foo".to_string()], &MatcherOptions::default()).unwrap();
+        let stats = find_lines(Cursor::new(&text), &re, &SearchOptions::default(), |_| {
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stats.matched_lines, 2);
+        assert_eq!(stats.matches, 3);
+        assert_eq!(stats.bytes_searched, text.len());
+
+        // Only the bytes read before max-count stopped the search are counted
+        let options = SearchOptions {
+            max_count: Some(1),
+            ..Default::default()
+        };
+        let stats = find_lines(Cursor::new(&text), &re, &options, |_| Ok(())).unwrap();
+        assert_eq!(stats.matches, 2);
+        assert_eq!(stats.bytes_searched, 8);
+    }
+
+    #[test]
+    fn test_find_lines_max_count() {
+        let text = b"foo\nbar\nfoo\nfoo\nbaz\nfoo\n";
+        let re = build_matcher(&["foo".to_string()], &MatcherOptions::default()).unwrap();
+
+        // Reading stops after the requested number of matches
+        let options = SearchOptions {
+            max_count: Some(2),
+            ..Default::default()
+        };
+        let lines = collect(&text[..], &re, options);
+        assert_eq!(describe(&lines), [":1", ":3"]);
+
+        // Trailing context is still printed, later matches only as context
+        let options = SearchOptions {
+            max_count: Some(2),
+            after_context: 2,
+            ..Default::default()
+        };
+        let lines = collect(&text[..], &re, options);
+        assert_eq!(describe(&lines), [":1", "-2", ":3", "-4", "-5"]);
+
+        // Zero matches means nothing is read at all
+        let options = SearchOptions {
+            max_count: Some(0),
+            ..Default::default()
+        };
+        let count = find_lines(Cursor::new(&text), &re, &options, |_| Ok(()));
+        assert_eq!(count.unwrap().matched_lines, 0);
+    }
+
+    #[test]
+    fn test_find_lines_records() {
+        let text = b"foo bar\nbaz\nbarbar\n";
+        let re = build_matcher(&["bar".to_string()], &MatcherOptions::default()).unwrap();
+        let lines = collect(&text[..], &re, SearchOptions::default());
+        assert_eq!(
+            lines,
+            vec![
+                Line::Match(Record {
+                    line_num: 1,
+                    offset: 0,
+                    text: b"foo bar\n".to_vec(),
+                    spans: vec![(4, 7)],
+                }),
+                Line::Match(Record {
+                    line_num: 3,
+                    offset: 12,
+                    text: b"barbar\n".to_vec(),
+                    spans: vec![(0, 3), (3, 6)],
+                }),
+            ]
+        );
+
+        // Inverted matches carry no spans
+        let lines = collect(
+            &text[..],
+            &re,
+            SearchOptions {
+                invert_match: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            lines,
+            vec![Line::Match(Record {
+                line_num: 2,
+                offset: 8,
+                text: b"baz\n".to_vec(),
+                spans: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_build_matcher() {
+        let patterns = |pats: &[&str]| pats.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        // Multiple patterns match wherever any one of them does
+        let m = build_matcher(&patterns(&["fo+", "ba[rz]"]), &MatcherOptions::default()).unwrap();
+        assert_eq!(m.find_iter(b"food baz"), [(0, 3), (5, 8)]);
+
+        // Fixed strings treat metacharacters literally, preferring the longest
+        let m = build_matcher(
+            &patterns(&["a.b", "a.bc"]),
+            &MatcherOptions {
+                fixed_strings: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter(b"axb a.bc"), [(4, 8)]);
+
+        // Case folding works for both engines
+        let m = build_matcher(
+            &patterns(&["FOO"]),
+            &MatcherOptions {
+                fixed_strings: true,
+                insensitive: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter(b"foo Foo"), [(0, 3), (4, 7)]);
+        let m = build_matcher(
+            &patterns(&["ÉTÉ"]),
+            &MatcherOptions {
+                fixed_strings: true,
+                insensitive: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter("été".as_bytes()), [(0, 5)]);
+
+        // Whole words skip matches embedded in longer words
+        let m = build_matcher(
+            &patterns(&["the"]),
+            &MatcherOptions {
+                word_regexp: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter(b"then the other_the the"), [(5, 8), (19, 22)]);
+        let m = build_matcher(
+            &patterns(&["the"]),
+            &MatcherOptions {
+                fixed_strings: true,
+                word_regexp: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter(b"then the other_the the"), [(5, 8), (19, 22)]);
+
+        // Whole lines must be matched from start to end
+        let m = build_matcher(
+            &patterns(&["fo+", "bar"]),
+            &MatcherOptions {
+                line_regexp: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter(b"foo"), [(0, 3)]);
+        assert!(m.find_iter(b"foo bar").is_empty());
+        let m = build_matcher(
+            &patterns(&["foo", "foo bar"]),
+            &MatcherOptions {
+                fixed_strings: true,
+                line_regexp: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(m.find_iter(b"foo bar"), [(0, 7)]);
+        assert!(m.find_iter(b"foo bar baz").is_empty());
+
+        // No patterns never match, a bad pattern is named in the error
+        let m = build_matcher(&[], &MatcherOptions::default()).unwrap();
+        assert!(m.find_iter(b"anything").is_empty());
+        let err = build_matcher(&patterns(&["ok", "*bad"]), &MatcherOptions::default());
+        assert_eq!(err.unwrap_err().to_string(), "Invalid pattern \"*bad\"");
+    }
+
+    #[test]
+    fn test_is_word_bounded() {
+        assert!(is_word_bounded(b"a foo b", 2, 5));
+        assert!(is_word_bounded(b"foo", 0, 3));
+        assert!(!is_word_bounded(b"afoo", 1, 4));
+        assert!(!is_word_bounded(b"foo_", 0, 3));
+
+        // Non-ASCII letters are word characters, dashes are not
+        assert!(!is_word_bounded("éfoo".as_bytes(), 2, 5));
+        assert!(is_word_bounded("—foo—".as_bytes(), 3, 6));
+    }
+
+    #[test]
+    fn test_json_line() {
+        let record = || Record {
+            line_num: 3,
+            offset: 12,
+            text: b"barbar\n".to_vec(),
+            spans: vec![(0, 3), (3, 6)],
+        };
+        let event = json_line("foo.txt", &Line::Match(record())).unwrap();
+        assert_eq!(
+            event.to_string(),
+            r#"{"type":"match","data":{"path":{"text":"foo.txt"},"lines":{"text":"barbar\n"},"line_number":3,"absolute_offset":12,"submatches":[{"match":{"text":"bar"},"start":0,"end":3},{"match":{"text":"bar"},"start":3,"end":6}]}}"#
+        );
+
+        let event = json_line("foo.txt", &Line::Context(record())).unwrap();
+        assert_eq!(event["type"], "context");
+
+        // Group separators have no JSON equivalent
+        assert!(json_line("foo.txt", &Line::Break).is_none());
+    }
+
+    #[test]
+    fn test_parse_sgr() {
+        assert_eq!(parse_sgr("01;31"), Colour::Red.bold());
+        assert_eq!(parse_sgr("4;38;5;208"), Colour::Fixed(208).underline());
+        assert_eq!(
+            parse_sgr("1;97;44"),
+            Colour::Fixed(15).bold().on(Colour::Blue)
+        );
+        assert_eq!(parse_sgr("1;0;32"), Colour::Green.normal());
+
+        // Empty or unrecognized codes leave the text unstyled
+        assert_eq!(parse_sgr(""), Style::new());
+        assert_eq!(parse_sgr("foo"), Style::new());
+    }
+
+    #[test]
+    fn test_parse_grep_colors() {
+        let defaults = parse_grep_colors("");
+        assert_eq!(defaults.matched, Colour::Red.bold());
+        assert_eq!(defaults.filename, Colour::Purple.normal());
+
+        // Recognized capabilities override the defaults, others are ignored
+        let colors = parse_grep_colors("ms=01;32:fn=:xx=1:ln");
+        assert_eq!(
+            colors,
+            Colors {
+                matched: Colour::Green.bold(),
+                filename: Style::new(),
+                ..defaults
+            }
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        let style = Colour::Red.bold();
+        let paint = |text: &[u8], spans: &[(usize, usize)], style: Style| {
+            let mut out = vec![];
+            highlight(&mut out, text, spans, style).unwrap();
+            out
+        };
+        assert_eq!(
+            paint(b"foo bar\n", &[(4, 7)], style),
+            format!("foo {}\n", style.paint("bar")).into_bytes()
+        );
+
+        // Bytes that are not valid UTF-8 pass through untouched
+        assert_eq!(
+            paint(b"caf\xe9 bar", &[(5, 8)], style),
+            [
+                &b"caf\xe9 "[..],
+                format!("{}", style.paint("bar")).as_bytes()
+            ]
+            .concat()
+        );
+
+        // Plain styles and empty spans add no escape sequences
+        assert_eq!(paint(b"foo\n", &[(0, 3)], Style::new()), b"foo\n");
+        assert_eq!(paint(b"foo\n", &[(1, 1)], style), b"foo\n");
+    }
+
+    #[test]
+    fn test_compression_detect() {
+        assert_eq!(
+            Compression::detect("foo", b"\x1f\x8b\x08"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::detect("foo", b"BZh91AY"),
+            Some(Compression::Bzip2)
+        );
+        assert_eq!(
+            Compression::detect("-", b"\xfd7zXZ\x00\x00"),
+            Some(Compression::Xz)
+        );
+        assert_eq!(
+            Compression::detect("foo", b"\x28\xb5\x2f\xfd"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::detect("foo.zst", b""), Some(Compression::Zstd));
+        assert_eq!(Compression::detect("foo.txt", b"The quick"), None);
+        assert_eq!(Compression::detect("-", b""), None);
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let expand = |template: &str| {
+            let group = |name: &str| match name {
+                "0" => Some(&b"foo bar"[..]),
+                "1" | "first" => Some(&b"foo"[..]),
+                _ => None,
+            };
+            let mut dst = vec![];
+            expand_template(template.as_bytes(), group, &mut dst);
+            String::from_utf8(dst).unwrap()
+        };
+        assert_eq!(expand("no groups"), "no groups");
+        assert_eq!(expand("[$0]"), "[foo bar]");
+        assert_eq!(expand("$1-${first}"), "foo-foo");
+        assert_eq!(expand("${1}x"), "foox");
+        // names are greedy, so "$1x" refers to a group called "1x"
+        assert_eq!(expand("$1x"), "");
+        assert_eq!(expand("$2 $missing"), " ");
+        assert_eq!(expand("$$1 costs $"), "$1 costs $");
+        assert_eq!(expand("${1"), "${1");
+    }
+
+    #[test]
+    fn test_replace() {
+        let matcher = build_matcher(&[r"(\w)o".to_string()], &MatcherOptions::default()).unwrap();
+        let text = b"foo boo";
+        let spans = matcher.find_iter(text);
+        assert_eq!(
+            matcher.replace(text, &spans, b"<$1>"),
+            (b"<f>o <b>o".to_vec(), vec![(0, 3), (5, 8)])
+        );
+
+        let matcher = build_matcher(
+            &["o".to_string()],
+            &MatcherOptions {
+                fixed_strings: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let spans = matcher.find_iter(text);
+        assert_eq!(
+            matcher.replace(text, &spans, b"$0$1"),
+            (b"foo boo".to_vec(), vec![(1, 2), (2, 3), (5, 6), (6, 7)])
+        );
+
+        // anchors and groups still match when the spans are found again
+        let matcher = build_matcher(&[r"(\w+)$".to_string()], &MatcherOptions::default()).unwrap();
+        let text = b"alpha beta";
+        let spans = matcher.find_iter(text);
+        assert_eq!(
+            matcher.replace(text, &spans, b"[$1]"),
+            (b"alpha [beta]".to_vec(), vec![(6, 12)])
+        );
+    }
+
+    #[test]
+    fn test_check_pattern() {
+        assert!(check_pattern("fo+", false).is_ok());
+        let err = check_pattern("*bad", false).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"*bad\"");
+
+        // Patterns only the backtracking engine can run point the user at -P
+        for pattern in ["foo(?!bar)", "(?<=x)y", r"(a)\1"] {
+            let err = check_pattern(pattern, false).unwrap_err();
+            assert!(err
+                .to_string()
+                .ends_with("look-around and backreferences need -P"));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pcre")]
+    fn test_build_matcher_perl() {
+        let perl = |pattern: &str, insensitive: bool| {
+            build_matcher(
+                &[pattern.to_string()],
+                &MatcherOptions {
+                    insensitive,
+                    perl: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let m = perl("password(?! \\[REDACTED\\])", false);
+        assert_eq!(m.find_iter(b"password [REDACTED] password"), [(20, 28)]);
+
+        let m = perl(r"(\w)\1", false);
+        assert_eq!(
+            m.find_iter(b"aardvark bookkeeper"),
+            [(0, 2), (10, 12), (12, 14), (14, 16)]
+        );
+
+        let m = perl("(?<=the )HEART", true);
+        assert_eq!(m.find_iter(b"The heart"), [(4, 9)]);
+
+        // Invalid UTF-8 splits the text into runs that are searched separately
+        let m = perl(r"(?<=a)b", false);
+        assert_eq!(m.find_iter(b"ab \xffb \xe9ab"), [(1, 2), (8, 9)]);
+
+        let m = perl(r"(?<first>\w+) (\w+)", false);
+        let spans = m.find_iter(b"\xff hello world");
+        assert_eq!(
+            m.replace(b"\xff hello world", &spans, b"$2 ${first}").0,
+            b"\xff world hello"
+        );
+
+        let err = check_pattern("(?<=a+)b", true).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"(?<=a+)b\"");
     }
 }
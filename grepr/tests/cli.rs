@@ -42,7 +42,7 @@ fn dies_no_args() -> TestResult {
 #[test]
 fn dies_bad_pattern() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["*foo", FOX])
+        .args(["*foo", FOX])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Invalid pattern \"*foo\""));
@@ -55,7 +55,7 @@ fn warns_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .args(&["foo", &bad])
+        .args(["foo", &bad])
         .assert()
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
@@ -72,7 +72,7 @@ fn run(args: &[&str], expected_file: &str) -> TestResult {
         expected_file
     };
 
-    let expected = fs::read_to_string(&expected_file)?;
+    let expected = fs::read_to_string(expected_file)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
@@ -234,7 +234,7 @@ fn warns_dir_not_recursive() -> TestResult {
     let stdout = "tests/inputs/fox.txt:\
         The quick brown fox jumps over the lazy dog.";
     Command::cargo_bin(PRG)?
-        .args(&["fox", INPUTS_DIR, FOX])
+        .args(["fox", INPUTS_DIR, FOX])
         .assert()
         .stderr(predicate::str::contains("tests/inputs is a directory"))
         .stdout(predicate::str::contains(stdout));
@@ -271,9 +271,993 @@ fn stdin_insensitive_count() -> TestResult {
     let expected = fs::read_to_string(expected_file)?;
 
     Command::cargo_bin(PRG)?
-        .args(&["-ci", "the", "-"])
+        .args(["-ci", "the", "-"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_context() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-A", "foo", "The", BUSTLE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "foo: invalid context length argument",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_after_context() -> TestResult {
+    run(
+        &["-A", "1", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.A1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nobody_before_context_insensitive() -> TestResult {
+    run(
+        &["-i", "--before-context", "2", "nobody", NOBODY],
+        "tests/expected/nobody.txt.insensitive.B2",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_context() -> TestResult {
+    run(
+        &["-C", "1", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.C1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_count_context() -> TestResult {
+    run(
+        &["-c", "-A", "1", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_files_with_matches_context() -> TestResult {
+    run(
+        &["-li", "-C", "1", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.insensitive.l",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_line_number() -> TestResult {
+    run(
+        &["-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nobody_only_matching_byte_offset() -> TestResult {
+    run(
+        &["--only-matching", "--byte-offset", "-i", "nobody", NOBODY],
+        "tests/expected/nobody.txt.insensitive.ob",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_line_number_context() -> TestResult {
+    run(
+        &["-n", "-C", "1", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.n.C1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_only_matching_line_number() -> TestResult {
+    run(
+        &["-noi", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.no",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_color() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--color=sometimes", "The", BUSTLE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("isn't a valid value"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "-n", "fox", FOX, EMPTY])
+        .env_remove("GREP_COLORS")
+        .assert()
+        .stdout(
+            "\u{1b}[35mtests/inputs/fox.txt\u{1b}[0m\u{1b}[36m:\u{1b}[0m\
+            \u{1b}[32m1\u{1b}[0m\u{1b}[36m:\u{1b}[0m\
+            The quick brown \u{1b}[1;31mfox\u{1b}[0m jumps over the lazy dog.\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always_grep_colors() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "-o", "fox", FOX])
+        .env("GREP_COLORS", "ms=04;34")
+        .assert()
+        .stdout("\u{1b}[4;34mfox\u{1b}[0m\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_never() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/bustle.txt.the.capitalized")?;
+    Command::cargo_bin(PRG)?
+        .args(["--color=never", "The", BUSTLE])
+        .env("GREP_COLORS", "ms=04;34")
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_threads() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-j", "lots", "The", BUSTLE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("lots: invalid thread count"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_insensitive_sorted() -> TestResult {
+    run(
+        &["-ri", "--sort=path", "the", INPUTS_DIR],
+        "tests/expected/all.the.recursive.insensitive.sorted",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_single_thread() -> TestResult {
+    run(
+        &[
+            "--threads",
+            "1",
+            "-C",
+            "1",
+            "The",
+            BUSTLE,
+            EMPTY,
+            FOX,
+            NOBODY,
+        ],
+        "tests/expected/all.the.capitalized.C1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_many_threads() -> TestResult {
+    run(
+        &["-j", "16", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_glob() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--include", "[a-", "The", INPUTS_DIR])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid glob \"[a-\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_max_depth() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--max-depth", "deep", "The", INPUTS_DIR])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("deep: invalid max depth"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include_exclude() -> TestResult {
+    run(
+        &[
+            "-ri",
+            "--sort=path",
+            "--include",
+            "*o*.txt",
+            "--exclude",
+            "bustle*",
+            "the",
+            INPUTS_DIR,
+        ],
+        "tests/expected/the.recursive.insensitive.include",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_max_depth_zero() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--max-depth", "0", "The", INPUTS_DIR])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("foo")
+        .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+        .assert()
+        .success()
+        .stdout("Binary file (standard input) matches\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("quux")
+        .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+        .assert()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_as_text() -> TestResult {
+    for flag in ["-a", "--binary-files=text"] {
+        Command::cargo_bin(PRG)?
+            .args([flag, "-n", "foo"])
+            .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+            .assert()
+            .stdout(&b"1:foo\x00bar\n2:baz foo\n"[..]);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_without_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--binary-files=without-match", "foo"])
+        .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+        .assert()
+        .stdout("");
+
+    Command::cargo_bin(PRG)?
+        .args(["--binary-files=without-match", "-c", "foo"])
+        .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+        .assert()
+        .stdout("0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "foo"])
+        .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+        .assert()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-o", "caf.|lait"])
+        .write_stdin(&b"caf\xe9 au lait\nthe\n"[..])
+        .assert()
+        .success()
+        .stdout("lait\n");
+
+    Command::cargo_bin(PRG)?
+        .arg("lait")
+        .write_stdin(&b"caf\xe9 au lait\nthe\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"caf\xe9 au lait\n"[..]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_no_pattern() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-i"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("USAGE"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pattern_file() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("{}: .* [(]os error 2[)]", bad);
+    Command::cargo_bin(PRG)?
+        .args(["-f", &bad, FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_patterns() -> TestResult {
+    run(
+        &["-e", "The", "-e", "nobody", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.nobody.e",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pattern_file() -> TestResult {
+    let pattern_file = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&pattern_file, "The\nnobody\n")?;
+    let result = run(
+        &[
+            "--file",
+            &pattern_file.display().to_string(),
+            BUSTLE,
+            EMPTY,
+            FOX,
+            NOBODY,
+        ],
+        "tests/expected/all.the.nobody.e",
+    );
+    fs::remove_file(&pattern_file)?;
+    result
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_pattern_file() -> TestResult {
+    let pattern_file = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&pattern_file, "")?;
+    let output = Command::cargo_bin(PRG)?
+        .args(["-f", &pattern_file.display().to_string(), BUSTLE])
+        .output()?;
+    fs::remove_file(&pattern_file)?;
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings_insensitive() -> TestResult {
+    run(
+        &["-Fi", "-e", "nobody!", "-e", "a FROG", NOBODY],
+        "tests/expected/nobody.txt.fixed.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_word() -> TestResult {
+    run(&["-w", "the", BUSTLE], "tests/expected/bustle.txt.the.word")
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_word_insensitive() -> TestResult {
+    run(
+        &["--word-regexp", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.insensitive.word",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_line() -> TestResult {
+    run(
+        &["-x", "-e", "Until eternity.", "-e", "And.*", BUSTLE],
+        "tests/expected/bustle.txt.line",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_max_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "few", "The", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("few: invalid max count"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_status() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["The", BUSTLE])
+        .assert()
+        .code(0);
+
+    Command::cargo_bin(PRG)?
+        .args(["nonesuch", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+
+    // An error wins over both, even when some file matched
+    Command::cargo_bin(PRG)?
+        .args(["The", BUSTLE, &gen_bad_file()])
+        .assert()
+        .code(2);
+
+    Command::cargo_bin(PRG)?
+        .args(["*foo", BUSTLE])
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "The", BUSTLE, EMPTY, FOX])
+        .assert()
+        .code(0)
+        .stdout("");
+
+    Command::cargo_bin(PRG)?
+        .args(["--quiet", "nonesuch", BUSTLE, EMPTY, FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+
+    // A match makes a quiet search succeed despite errors
+    Command::cargo_bin(PRG)?
+        .args(["-q", "The", &gen_bad_file(), BUSTLE])
+        .assert()
+        .code(0)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches() -> TestResult {
+    run(
+        &["-li", "nobody", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.nobody.insensitive.l",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_without_match() -> TestResult {
+    run(
+        &[
+            "--files-without-match",
+            "-i",
+            "nobody",
+            BUSTLE,
+            EMPTY,
+            FOX,
+            NOBODY,
+        ],
+        "tests/expected/all.nobody.insensitive.L",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count() -> TestResult {
+    run(
+        &["-m", "2", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.m2",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_context() -> TestResult {
+    run(
+        &["--max-count", "1", "-A", "2", "-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.m1.A2",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_count() -> TestResult {
+    run(
+        &["-c", "-m", "2", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.m2.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn json() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--json", "-A", "1", "-i", "nobody", BUSTLE, NOBODY])
+        .output()?;
+    assert!(output.status.success());
+
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let types: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        types,
+        ["begin", "match", "match", "context", "end", "summary"]
+    );
+
+    // Only the file with matches gets begin and end events
+    assert_eq!(events[0]["data"]["path"]["text"], NOBODY);
+    assert_eq!(events[1]["data"]["line_number"], 1);
+    assert_eq!(events[1]["data"]["absolute_offset"], 0);
+    assert_eq!(
+        events[1]["data"]["submatches"][0]["match"]["text"],
+        "Nobody"
+    );
+    assert_eq!(events[1]["data"]["submatches"][0]["start"], 4);
+    assert_eq!(events[1]["data"]["submatches"][0]["end"], 10);
+    assert_eq!(events[2]["data"]["absolute_offset"], 26);
+    assert_eq!(events[3]["data"]["line_number"], 3);
+    assert_eq!(events[3]["data"]["submatches"], serde_json::json!([]));
+    assert_eq!(events[4]["data"]["stats"]["matched_lines"], 2);
+    assert_eq!(events[5]["data"]["stats"]["searches"], 2);
+    assert_eq!(events[5]["data"]["stats"]["searches_with_match"], 1);
+    assert_eq!(events[5]["data"]["stats"]["matches"], 2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_multiple_files_context() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--json", "-A", "1", "The", BUSTLE, FOX])
+        .output()?;
+    assert!(output.status.success());
+
+    // Every line is an event, with no separator between the files
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let types: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        types,
+        [
+            "begin", "match", "match", "context", "match", "context", "end", "begin", "match",
+            "end", "summary"
+        ]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_binary_matches() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--json", "foo"])
+        .write_stdin(&b"foo\x00bar\nbaz foo\n"[..])
+        .output()?;
+    assert!(output.status.success());
+
+    // The lines of a binary file stay hidden, but its match is still reported
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let types: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["begin", "end", "summary"]);
+    assert_eq!(events[1]["data"]["stats"]["matched_lines"], 1);
+    assert_eq!(events[2]["data"]["stats"]["matched_lines"], 1);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_conflicts() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--json", "-c", "The", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+fn compress(filename: &str, extension: &str) -> Result<String, Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    let text = fs::read(filename)?;
+    let path = std::env::temp_dir().join(format!("{}.{}", gen_bad_file(), extension));
+    let file = fs::File::create(&path)?;
+    match extension {
+        "gz" => {
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            encoder.write_all(&text)?;
+            encoder.finish()?;
+        }
+        "bz2" => {
+            let mut encoder = bzip2::write::BzEncoder::new(file, bzip2::Compression::default());
+            encoder.write_all(&text)?;
+            encoder.finish()?;
+        }
+        "xz" => {
+            let mut encoder = xz2::write::XzEncoder::new(file, 6);
+            encoder.write_all(&text)?;
+            encoder.finish()?;
+        }
+        _ => zstd::stream::copy_encode(&text[..], file, 0)?,
+    }
+    Ok(path.display().to_string())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/bustle.txt.the.capitalized")?;
+    for extension in ["gz", "bz2", "xz", "zst"] {
+        let path = compress(BUSTLE, extension)?;
+        let output = Command::cargo_bin(PRG)?
+            .args(["-z", "The", &path])
+            .output()?;
+        fs::remove_file(&path)?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout)?, expected);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_plain_and_stdin() -> TestResult {
+    // Uncompressed input passes through untouched
+    run(
+        &["-z", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized",
+    )?;
+
+    let path = compress(BUSTLE, "gz")?;
+    let input = fs::read(&path)?;
+    fs::remove_file(&path)?;
+    let expected = fs::read_to_string("tests/expected/bustle.txt.the.capitalized")?;
+    Command::cargo_bin(PRG)?
+        .args(["-z", "The"])
         .write_stdin(input)
         .assert()
+        .success()
         .stdout(expected);
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn compressed_without_search_zip() -> TestResult {
+    let path = compress(BUSTLE, "gz")?;
+    let output = Command::cargo_bin(PRG)?.args(["The", &path]).output()?;
+    fs::remove_file(&path)?;
+    assert_eq!(output.status.code(), Some(1));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_replace() -> TestResult {
+    run(
+        &["--replace", "[$1]", r"(\w+)ing", BUSTLE],
+        "tests/expected/bustle.txt.ing.replace",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_only_matching_named_group() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--replace", "${word}!", r"(?P<word>\bT\w+)", BUSTLE])
+        .assert()
+        .success()
+        .stdout("The!\nThe!\nThe!\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dry_run() -> TestResult {
+    let before = fs::read_to_string(BUSTLE)?;
+    run(
+        &[
+            "--replace",
+            "X$$",
+            "--in-place",
+            "--dry-run",
+            "The|love",
+            BUSTLE,
+        ],
+        "tests/expected/bustle.txt.the.love.dry-run",
+    )?;
+    assert_eq!(fs::read_to_string(BUSTLE)?, before);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place() -> TestResult {
+    let path = std::env::temp_dir().join(gen_bad_file());
+    fs::copy(BUSTLE, &path)?;
+    let filename = path.display().to_string();
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "$0$0", "--in-place", "-F", "love", &filename])
+        .assert()
+        .success()
+        .stdout("");
+    let contents = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    assert_eq!(
+        contents,
+        fs::read_to_string(BUSTLE)?.replace("love", "lovelove")
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_same_file_twice() -> TestResult {
+    let path = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&path, "a foo\n")?;
+    let filename = path.display().to_string();
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "XX", "--in-place", "foo", &filename, &filename])
+        .assert()
+        .success();
+    let contents = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    assert_eq!(contents, "a XX\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_anchored() -> TestResult {
+    let path = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&path, "alpha beta\ngamma\n")?;
+    let filename = path.display().to_string();
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "[$1]", "--in-place", r"(\w+)$", &filename])
+        .assert()
+        .success();
+    let contents = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    assert_eq!(contents, "alpha [beta]\n[gamma]\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "x", "--in-place", "zebra", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "x", "--in-place", "The"])
+        .write_stdin("The end\n")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot edit standard input"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_conflicts_with_quiet() -> TestResult {
+    // -q stops at the first match, which would leave later files unedited
+    Command::cargo_bin(PRG)?
+        .args(["-q", "--replace", "x", "--in-place", "The", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_requires_replace() -> TestResult {
+    for args in [
+        &["--in-place", "The", BUSTLE][..],
+        &["--dry-run", "The", BUSTLE],
+    ] {
+        Command::cargo_bin(PRG)?.args(args).assert().code(2);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline() -> TestResult {
+    run(
+        &["-U", "-n", r"earth,—\n\nThe", BUSTLE],
+        "tests/expected/bustle.txt.multiline.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_conflicts_with_replace() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "--replace", "<$0>", r"fox\njumps", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_needs_flag() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([r"death\nIs", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-n", "-A1", "a"])
+        .write_stdin("a b\0c\0d\0e\0a\0")
+        .assert()
+        .success()
+        .stdout("1:a b\x002-c\0--\n5:a\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data_only_matching() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-o", "-b", "a"])
+        .write_stdin("a b\nc a\0")
+        .assert()
+        .success()
+        .stdout("0:a\x006:a\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_lookaround_without_perl() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([r"The (?!morning)\w+", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("need -P"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(feature = "pcre"))]
+fn dies_perl_unavailable() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "The", BUSTLE])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--features pcre"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre")]
+fn perl_lookahead() -> TestResult {
+    run(
+        &["-P", r"The (?!morning)\w+", BUSTLE],
+        "tests/expected/bustle.txt.lookahead",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(feature = "pcre")]
+fn perl_backreference() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-o", r"(\w)\1", BUSTLE])
+        .assert()
+        .success()
+        .stdout("ee\ntt\nll\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/all.the.capitalized")?;
+    let output = Command::cargo_bin(PRG)?
+        .args(["--stats", "The", BUSTLE, EMPTY, FOX, NOBODY])
+        .output()?;
+    assert!(output.status.success());
+
+    // The results come first, then the summary after a blank line
+    let stdout = String::from_utf8(output.stdout)?;
+    let (results, summary) = stdout.split_once("\n\n").unwrap();
+    assert_eq!(format!("{}\n", results), expected);
+    let lines: Vec<_> = summary.lines().collect();
+    assert_eq!(
+        lines[..5],
+        [
+            "5 matches",
+            "5 matched lines",
+            "3 files contained matches",
+            "4 files searched",
+            "486 bytes searched",
+        ]
+    );
+    for (line, label) in lines[5..].iter().zip([
+        "spent finding files",
+        "spent searching",
+        "spent matching",
+        "",
+    ]) {
+        let seconds = line.split(" seconds").next().unwrap();
+        assert!(seconds.parse::<f64>().is_ok());
+        assert!(line.ends_with(label));
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const PRG: &str = env!("CARGO_BIN_EXE_tailr");
+
+// Each case is one of the TakeValue edge cases from the unit tests for
+// get_start_index, as the options that produce it
+const LINE_CASES: [(&str, &str); 7] = [
+    ("last 10", "10"),
+    ("none", "0"),
+    ("plus zero", "+0"),
+    ("from first", "+1"),
+    ("from middle", "+500000"),
+    ("beyond the start", "2000000"),
+    ("beyond the end", "+2000000"),
+];
+
+const BYTE_CASES: [(&str, &str); 4] = [
+    ("last 10", "10"),
+    ("none", "0"),
+    ("from middle", "+33554432"),
+    ("beyond the end", "+999999999"),
+];
+
+// A log of about 64 MiB in a million lines of varying length, made once
+fn big_log() -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("big.log");
+    if fs::metadata(&path).is_err() {
+        let mut out = BufWriter::new(File::create(&path).unwrap());
+        for i in 0..1_000_000 {
+            writeln!(out, "{:>8} {}", i, "log line ".repeat(i % 13)).unwrap();
+        }
+    }
+    path
+}
+
+fn tailr(args: &[&str], path: &Path) {
+    let status = Command::new(PRG)
+        .args(args)
+        .arg(path)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn bench_tail(c: &mut Criterion) {
+    let path = big_log();
+
+    let mut group = c.benchmark_group("lines");
+    group.sample_size(20);
+    for (name, num) in LINE_CASES {
+        group.bench_with_input(BenchmarkId::from_parameter(name), num, |b, num| {
+            b.iter(|| tailr(&["-n", num], &path))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("bytes");
+    group.sample_size(20);
+    for (name, num) in BYTE_CASES {
+        group.bench_with_input(BenchmarkId::from_parameter(name), num, |b, num| {
+            b.iter(|| tailr(&["-c", num], &path))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tail);
+criterion_main!(benches);
@@ -1,17 +1,26 @@
 use crate::TakeValue::*;
 use clap::{App, Arg};
+use notify::{RecursiveMode, Watcher};
 use once_cell::sync::OnceCell;
 use regex::Regex;
 use std::{
+    collections::VecDeque,
     error::Error,
-    fs::File,
-    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    mem,
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::Duration,
 };
 
 static NUM_RE: OnceCell<Regex> = OnceCell::new();
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
+const BLOCK_SIZE: usize = 64 * 1024;
+
 #[derive(Debug, PartialEq)]
 enum TakeValue {
     PlusZero,
@@ -24,6 +33,33 @@ pub struct Config {
     lines: TakeValue,
     bytes: Option<TakeValue>,
     quiet: bool,
+    verbose: bool,
+    terminator: u8, // the byte that ends each line
+    follow: Option<Follow>,
+    pid: Option<u32>,
+    sleep_interval: Duration,
+}
+
+/// How a file is tracked once its tail has been printed
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Follow {
+    Descriptor, // keep reading the file that was opened, even after a rename
+    Name,       // reopen the name whenever the file there is replaced or recreated
+}
+
+/// An opened input, which can only be read through once unless it is a regular file
+enum Input {
+    File(File),
+    Stream(Box<dyn BufRead>),
+}
+
+/// A file whose appended data is printed as it arrives
+#[derive(Debug)]
+struct Followed {
+    filename: String,
+    file: Option<File>, // None while a file followed by name is missing
+    position: u64,      // how much of the file has been printed
+    open_failed: bool,  // the name exists but could not be opened, which was reported
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -34,14 +70,14 @@ pub fn get_args() -> MyResult<Config> {
         .arg(
             Arg::with_name("files")
                 .value_name("FILE")
-                .help("Input file(s)")
+                .help("Input file(s), - for stdin")
                 .multiple(true)
-                .required(true),
+                .default_value("-"),
         )
         .arg(
             Arg::with_name("lines")
                 .value_name("LINES")
-                .help("Number of lines")
+                .help("Number of lines, optionally with a size suffix like K, KB or MiB")
                 .short("n")
                 .long("lines")
                 .takes_value(true)
@@ -50,7 +86,7 @@ pub fn get_args() -> MyResult<Config> {
         .arg(
             Arg::with_name("bytes")
                 .value_name("BYTES")
-                .help("Number of bytes")
+                .help("Number of bytes, optionally with a size suffix like K, KB or MiB")
                 .short("c")
                 .long("bytes")
                 .takes_value(true)
@@ -61,8 +97,57 @@ pub fn get_args() -> MyResult<Config> {
                 .help("Suppress headers")
                 .short("q")
                 .long("quiet")
+                .takes_value(false)
+                .overrides_with("verbose"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .help("Always print headers, even for a single file")
+                .short("v")
+                .long("verbose")
+                .takes_value(false)
+                .overrides_with("quiet"),
+        )
+        .arg(
+            Arg::with_name("zero_terminated")
+                .help("Line delimiter is NUL, not newline")
+                .short("z")
+                .long("zero-terminated")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .value_name("HOW")
+                .help("Output appended data as the file grows; HOW is descriptor or name")
+                .short("f")
+                .long("follow")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .possible_values(&["descriptor", "name"]),
+        )
+        .arg(
+            Arg::with_name("follow_name")
+                .help("Same as --follow=name, waiting for files that do not exist yet")
+                .short("F")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("pid")
+                .value_name("PID")
+                .help("With -f, terminate after process PID dies")
+                .long("pid")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sleep_interval")
+                .value_name("SECONDS")
+                .help("With -f, check the files at least every SECONDS")
+                .short("s")
+                .long("sleep-interval")
+                .takes_value(true)
+                .default_value("1.0"),
+        )
         .get_matches();
 
     let lines = parse_num(matches.value_of("lines").unwrap())
@@ -72,75 +157,456 @@ pub fn get_args() -> MyResult<Config> {
         .map(parse_num)
         .transpose()
         .map_err(|e| format!("illegal byte count -- {}", e))?;
+    let follow = if matches.is_present("follow_name") {
+        Some(Follow::Name)
+    } else if matches.is_present("follow") {
+        match matches.value_of("follow") {
+            Some("name") => Some(Follow::Name),
+            _ => Some(Follow::Descriptor),
+        }
+    } else {
+        None
+    };
+    let pid = matches
+        .value_of("pid")
+        .map(|pid| {
+            pid.parse()
+                .map_err(|_| format!("illegal process id -- {}", pid))
+        })
+        .transpose()?;
+    let interval = matches.value_of("sleep_interval").unwrap();
+    let sleep_interval = interval
+        .parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("illegal sleep interval -- {}", interval))?;
 
     Ok(Config {
         files: matches.values_of_lossy("files").unwrap(),
         lines,
         bytes,
         quiet: matches.is_present("quiet"),
+        verbose: matches.is_present("verbose"),
+        terminator: if matches.is_present("zero_terminated") {
+            b'\0'
+        } else {
+            b'\n'
+        },
+        follow,
+        pid,
+        sleep_interval,
     })
 }
 
 pub fn run(config: Config) -> MyResult<()> {
     let num_files = config.files.len();
+    let headers = config.verbose || (!config.quiet && num_files > 1);
+    let mut followed = vec![];
+    let mut any_opened = false;
+    // which followed file the output last came from, as its header is the one showing
+    let mut last = None;
     for (file_num, filename) in config.files.iter().enumerate() {
-        match File::open(&filename) {
-            Err(e) => eprintln!("{}: {}", filename, e),
-            Ok(file) => {
-                if !config.quiet && num_files > 1 {
+        match open(filename) {
+            Err(e) => {
+                eprintln!("{}: {}", filename, e);
+                // a name is watched until a file turns up there
+                if config.follow == Some(Follow::Name) {
+                    followed.push(Followed {
+                        filename: filename.to_string(),
+                        file: None,
+                        position: 0,
+                        open_failed: true,
+                    });
+                }
+            }
+            Ok(input) => {
+                any_opened = true;
+                last = None;
+                if headers {
                     println!(
                         "{}==> {} <==",
                         if file_num > 0 { "\n" } else { "" },
-                        filename
+                        if filename == "-" {
+                            "standard input"
+                        } else {
+                            filename
+                        }
                     );
                 }
-                let (total_lines, total_bytes) = count_lines_bytes(filename)?;
-                let file = BufReader::new(file);
-                if let Some(num_bytes) = &config.bytes {
-                    print_bytes(file, num_bytes, total_bytes)?;
-                } else {
-                    print_lines(file, &config.lines, total_lines)?;
+                match (input, &config.bytes) {
+                    (Input::Stream(stream), Some(num_bytes)) => {
+                        print_bytes_stream(stream, num_bytes)?;
+                    }
+                    (Input::Stream(stream), None) => {
+                        print_lines_stream(stream, &config.lines, config.terminator)?
+                    }
+                    (Input::File(file), num_bytes) => {
+                        // following picks up where the tail stopped, so nothing
+                        // appended while it was printed is lost
+                        let position = match num_bytes {
+                            Some(num_bytes) => print_bytes(&file, num_bytes)?,
+                            None => print_lines(&file, &config.lines, config.terminator)?,
+                        };
+                        if config.follow.is_some() {
+                            last = Some(followed.len());
+                            followed.push(Followed {
+                                filename: filename.to_string(),
+                                position,
+                                file: Some(file),
+                                open_failed: false,
+                            });
+                        }
+                    }
                 }
             }
         }
     }
 
-    Ok(())
+    match config.follow {
+        // a pipe that has been read to the end is over, so only files are followed
+        Some(how) if !followed.is_empty() => follow(followed, how, headers, last, &config),
+        Some(_) if !any_opened => Err(From::from("no files remaining")),
+        _ => Ok(()),
+    }
 }
 
-fn print_lines(mut file: impl BufRead, num_lines: &TakeValue, total_lines: i64) -> MyResult<()> {
-    if let Some(start) = get_start_index(num_lines, total_lines) {
-        let mut line_num = 0;
-        let mut buf = Vec::new();
-        loop {
-            let bytes_read = file.read_until(b'\n', &mut buf)?;
-            if bytes_read == 0 {
-                break;
+// Regular files are read by seeking, anything else as a stream
+fn open(filename: &str) -> MyResult<Input> {
+    if filename == "-" {
+        return Ok(Input::Stream(Box::new(BufReader::new(io::stdin()))));
+    }
+    let file = File::open(filename)?;
+    if file.metadata()?.is_file() {
+        Ok(Input::File(file))
+    } else {
+        Ok(Input::Stream(Box::new(BufReader::new(file))))
+    }
+}
+
+// Print whatever is appended to the files until the --pid process exits, if ever,
+// with a header each time the output switches to another file
+fn follow(
+    mut files: Vec<Followed>,
+    how: Follow,
+    headers: bool,
+    mut last: Option<usize>,
+    config: &Config,
+) -> MyResult<()> {
+    let mut out = io::stdout().lock();
+    let mut buf = vec![0; BLOCK_SIZE];
+
+    // inotify wakes the loop as soon as anything changes, and where it is
+    // not available the files are polled every sleep interval instead
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).ok();
+    if let Some(watcher) = watcher.as_mut() {
+        for followed in &files {
+            // watching the directory sees the name being replaced or recreated
+            let path = Path::new(&followed.filename);
+            let path = match (how, path.parent()) {
+                (Follow::Name, Some(dir)) if dir.as_os_str().is_empty() => Path::new("."),
+                (Follow::Name, Some(dir)) => dir,
+                _ => path,
+            };
+            let _ = watcher.watch(path, RecursiveMode::NonRecursive);
+        }
+    }
+
+    loop {
+        // checking first lets whatever the process wrote before exiting be printed
+        let exited = config.pid.is_some_and(|pid| !process_exists(pid));
+        // files take turns a block at a time so a busy one cannot hold up the rest
+        let mut busy = true;
+        while busy {
+            busy = false;
+            for (file_num, followed) in files.iter_mut().enumerate() {
+                let len = followed.read_new(&mut buf)?;
+                if len > 0 {
+                    if headers && last != Some(file_num) {
+                        writeln!(out, "\n==> {} <==", followed.filename)?;
+                    }
+                    out.write_all(&buf[..len])?;
+                    last = Some(file_num);
+                    busy = true;
+                } else if how == Follow::Name && followed.reopen() {
+                    busy = true;
+                }
             }
-            if line_num >= start {
-                print!("{}", String::from_utf8_lossy(&buf));
+        }
+        out.flush()?;
+        if exited {
+            return Ok(());
+        }
+
+        match watcher {
+            Some(_) => {
+                let _ = rx.recv_timeout(config.sleep_interval);
+                while rx.try_recv().is_ok() {}
             }
-            line_num += 1;
-            buf.clear();
+            None => thread::sleep(config.sleep_interval),
         }
     }
+}
 
-    Ok(())
+impl Followed {
+    // Read the next block past the last position, starting over if the file shrank
+    fn read_new(&mut self, buf: &mut [u8]) -> MyResult<usize> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(0);
+        };
+        if file.metadata()?.len() < self.position {
+            eprintln!("{}: file truncated", self.filename);
+            self.position = 0;
+        }
+        file.seek(SeekFrom::Start(self.position))?;
+        let len = file.read(buf)?;
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    // Switch to the file now found under the name, returning whether it changed.
+    // A name that cannot be opened is reported once and then retried quietly.
+    fn reopen(&mut self) -> bool {
+        let current = fs::metadata(&self.filename).ok();
+        let open = self.file.as_ref().and_then(|file| file.metadata().ok());
+        let message = match (open, current) {
+            (Some(_), None) => {
+                eprintln!("{}: file has become inaccessible", self.filename);
+                self.file = None;
+                return false;
+            }
+            (Some(open), Some(current)) if !same_file(&open, &current) => {
+                "file has been replaced; following new file"
+            }
+            (None, Some(_)) if self.open_failed => "file has become accessible",
+            (None, Some(_)) => "file has appeared; following new file",
+            (None, None) => {
+                self.open_failed = false;
+                return false;
+            }
+            _ => return false,
+        };
+        self.position = 0;
+        match File::open(&self.filename) {
+            Ok(file) => {
+                eprintln!("{}: {}", self.filename, message);
+                self.file = Some(file);
+                self.open_failed = false;
+                true
+            }
+            Err(e) => {
+                if !self.open_failed {
+                    eprintln!("{}: {}", self.filename, e);
+                }
+                self.file = None;
+                self.open_failed = true;
+                false
+            }
+        }
+    }
+}
+
+// Whether two looks at a name found the same file
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+// Without inode numbers, a file recreated under the name shows a new creation time
+#[cfg(not(unix))]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.created().ok() == b.created().ok()
+}
+
+// Signal 0 checks that the process exists without disturbing it
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: kill only takes integers, and signal 0 sends nothing, it just checks the pid
+    unsafe {
+        libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+// Elsewhere ask tasklist, and keep following if it cannot say
+#[cfg(not(unix))]
+fn process_exists(pid: u32) -> bool {
+    match std::process::Command::new("tasklist")
+        .args(["/NH", "/FI", &format!("PID eq {}", pid)])
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .any(|word| word == pid.to_string()),
+        Err(_) => true,
+    }
+}
+
+// Print the tail of the file, returning the offset printed up to
+fn print_lines(mut file: impl Read + Seek, num_lines: &TakeValue, terminator: u8) -> MyResult<u64> {
+    let start = match num_lines {
+        PlusZero => 0,
+        TakeNum(0) => return Ok(file.seek(SeekFrom::End(0))?),
+        TakeNum(take) if take.is_positive() => {
+            skip_lines(&mut file, take.unsigned_abs() - 1, terminator)?
+        }
+        TakeNum(take) => find_tail_start(&mut file, take.unsigned_abs(), terminator)?,
+    };
+    print_from(file, start)
+}
+
+// Print the last bytes of the file, returning the offset printed up to
+fn print_bytes(mut file: impl Read + Seek, num_bytes: &TakeValue) -> MyResult<u64> {
+    let total_bytes = file.seek(SeekFrom::End(0))?;
+    match get_start_index(num_bytes, i64::try_from(total_bytes)?) {
+        Some(start) => print_from(file, start),
+        None => Ok(total_bytes),
+    }
 }
 
-fn print_bytes<T>(mut file: T, num_bytes: &TakeValue, total_bytes: i64) -> MyResult<()>
-where
-    T: Read + Seek,
-{
-    if let Some(start) = get_start_index(num_bytes, total_bytes) {
+// Copy the file from `start` to the end straight to stdout, returning where it ended
+fn print_from(mut file: impl Read + Seek, start: u64) -> MyResult<u64> {
+    file.seek(SeekFrom::Start(start))?;
+    let copied = io::copy(&mut file, &mut io::stdout().lock())?;
+    Ok(start + copied)
+}
+
+// Scan backwards from the end a block at a time for where the last `num_lines`
+// lines start, so the cost follows the size of the tail rather than of the file
+fn find_tail_start(mut file: impl Read + Seek, num_lines: u64, terminator: u8) -> MyResult<u64> {
+    let total_bytes = file.seek(SeekFrom::End(0))?;
+    let mut buf = vec![0; BLOCK_SIZE];
+    let mut end = total_bytes;
+    let mut found = 0;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK_SIZE as u64);
+        let block = &mut buf[..(end - start) as usize];
         file.seek(SeekFrom::Start(start))?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        if !buffer.is_empty() {
-            print!("{}", String::from_utf8_lossy(&buffer));
+        file.read_exact(block)?;
+
+        for (i, _) in block
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, &b)| b == terminator)
+        {
+            let offset = start + i as u64;
+            // the terminator that ends the file closes the last line rather than starting one
+            if offset + 1 == total_bytes {
+                continue;
+            }
+            found += 1;
+            if found == num_lines {
+                return Ok(offset + 1);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// Read forward past `num_lines` lines, returning where the next one starts
+fn skip_lines(mut file: impl Read + Seek, num_lines: u64, terminator: u8) -> MyResult<u64> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut file = BufReader::with_capacity(BLOCK_SIZE, file);
+    let mut offset = 0;
+    let mut remaining = num_lines;
+    while remaining > 0 {
+        let buf = file.fill_buf()?;
+        if buf.is_empty() {
+            break;
         }
+        for (i, _) in buf.iter().enumerate().filter(|(_, &b)| b == terminator) {
+            remaining -= 1;
+            if remaining == 0 {
+                return Ok(offset + i as u64 + 1);
+            }
+        }
+        let len = buf.len();
+        offset += len as u64;
+        file.consume(len);
     }
+    Ok(offset)
+}
 
+// Keep only the last lines of a stream in a ring as it goes past, since it
+// cannot be read again once its length is known
+fn print_lines_stream(
+    mut stream: impl BufRead,
+    num_lines: &TakeValue,
+    terminator: u8,
+) -> MyResult<()> {
+    let mut out = io::stdout().lock();
+    match num_lines {
+        PlusZero => {
+            io::copy(&mut stream, &mut out)?;
+        }
+        TakeNum(0) => {}
+        TakeNum(take) if take.is_positive() => {
+            let mut line = vec![];
+            for _ in 1..*take {
+                line.clear();
+                if stream.read_until(terminator, &mut line)? == 0 {
+                    return Ok(());
+                }
+            }
+            io::copy(&mut stream, &mut out)?;
+        }
+        TakeNum(take) => {
+            let num_lines = usize::try_from(take.unsigned_abs()).unwrap_or(usize::MAX);
+            let mut ring = VecDeque::new();
+            let mut line = vec![];
+            while stream.read_until(terminator, &mut line)? > 0 {
+                if ring.len() == num_lines {
+                    ring.pop_front();
+                }
+                ring.push_back(mem::take(&mut line));
+            }
+            for line in ring {
+                out.write_all(&line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The same for bytes, where the ring holds the last `num_bytes` bytes
+fn print_bytes_stream(mut stream: impl BufRead, num_bytes: &TakeValue) -> MyResult<()> {
+    let mut out = io::stdout().lock();
+    match num_bytes {
+        PlusZero => {
+            io::copy(&mut stream, &mut out)?;
+        }
+        TakeNum(0) => {}
+        TakeNum(take) if take.is_positive() => {
+            let skip = take.unsigned_abs() - 1;
+            if io::copy(&mut (&mut stream).take(skip), &mut io::sink())? == skip {
+                io::copy(&mut stream, &mut out)?;
+            }
+        }
+        TakeNum(take) => {
+            let num_bytes = usize::try_from(take.unsigned_abs()).unwrap_or(usize::MAX);
+            let mut ring = VecDeque::new();
+            loop {
+                let buf = stream.fill_buf()?;
+                if buf.is_empty() {
+                    break;
+                }
+                ring.extend(buf);
+                let len = buf.len();
+                stream.consume(len);
+                if ring.len() > num_bytes {
+                    ring.drain(..ring.len() - num_bytes);
+                }
+            }
+            let (front, back) = ring.as_slices();
+            out.write_all(front)?;
+            out.write_all(back)?;
+        }
+    }
     Ok(())
 }
 
@@ -175,39 +641,22 @@ fn get_start_index(take_val: &TakeValue, total: i64) -> Option<u64> {
     }
 }
 
-fn count_lines_bytes(filename: &str) -> MyResult<(i64, i64)> {
-    let mut file = BufReader::new(File::open(filename)?);
-    let mut num_lines = 0;
-    let mut num_bytes = 0;
-    let mut buf = Vec::new();
-    loop {
-        let bytes_read = file.read_until(b'\n', &mut buf)?;
-        if bytes_read == 0 {
-            break;
-        }
-        num_lines += 1;
-        num_bytes += bytes_read as i64;
-        buf.clear();
-    }
-    Ok((num_lines, num_bytes))
-}
-
 fn parse_num(input: &str) -> MyResult<TakeValue> {
-    let num_re = NUM_RE.get_or_init(|| Regex::new(r"^([+|-])?(\d+)$").unwrap());
+    let num_re = NUM_RE.get_or_init(|| Regex::new(r"^([+-])?(\d+)([[:alpha:]]*)$").unwrap());
 
     match num_re.captures(input) {
         Some(caps) => {
             let sign = caps.get(1).map_or("-", |m| m.as_str()); // assume negative default
             let num = format!("{}{}", sign, caps.get(2).unwrap().as_str());
-
-            if let Ok(val) = num.parse() {
-                if sign == "+" && val == 0 {
-                    Ok(PlusZero)
-                } else {
-                    Ok(TakeNum(val))
-                }
-            } else {
-                Err(From::from(input))
+            let multiplier = parse_suffix(caps.get(3).unwrap().as_str());
+
+            match (num.parse::<i64>(), multiplier) {
+                (Ok(val), Some(multiplier)) => match val.checked_mul(multiplier) {
+                    Some(0) if sign == "+" => Ok(PlusZero),
+                    Some(val) => Ok(TakeNum(val)),
+                    None => Err(From::from(input)),
+                },
+                _ => Err(From::from(input)),
             }
         }
 
@@ -215,9 +664,32 @@ fn parse_num(input: &str) -> MyResult<TakeValue> {
     }
 }
 
+// GNU size suffixes: b is 512, K or KiB is 1024, KB is 1000, and so on through E
+fn parse_suffix(suffix: &str) -> Option<i64> {
+    if suffix.is_empty() {
+        return Some(1);
+    }
+    if suffix == "b" {
+        return Some(512);
+    }
+    let mut chars = suffix.chars();
+    let power = "KMGTPE".find(chars.next()?.to_ascii_uppercase())? as u32 + 1;
+    let base: i64 = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return None,
+    };
+    base.checked_pow(power)
+}
+
 #[cfg(test)]
 mod unit_tests {
-    use super::{count_lines_bytes, get_start_index, parse_num, TakeValue::*};
+    use super::{
+        find_tail_start, get_start_index, parse_num, parse_suffix, skip_lines, TakeValue::*,
+        BLOCK_SIZE,
+    };
+    use std::fs::File;
+    use std::io::Cursor;
 
     #[test]
     fn test_parse_num() {
@@ -272,17 +744,109 @@ mod unit_tests {
         let res = parse_num("foo");
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().to_string(), "foo");
-    }
 
-    #[test]
-    fn test_count_lines_bytes() {
-        let res = count_lines_bytes("tests/inputs/one.txt");
+        // A size suffix multiplies the number, keeping its sign
+        let res = parse_num("2K");
         assert!(res.is_ok());
-        assert_eq!(res.unwrap(), (1, 24));
+        assert_eq!(res.unwrap(), TakeNum(-2048));
 
-        let res = count_lines_bytes("tests/inputs/ten.txt");
+        let res = parse_num("+1KB");
         assert!(res.is_ok());
-        assert_eq!(res.unwrap(), (10, 49));
+        assert_eq!(res.unwrap(), TakeNum(1000));
+
+        let res = parse_num("+0M");
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), PlusZero);
+
+        // A suffix that overflows is invalid
+        let res = parse_num("9E");
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), "9E");
+
+        // An unknown suffix is invalid
+        let res = parse_num("3X");
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), "3X");
+    }
+
+    #[test]
+    fn test_parse_suffix() {
+        assert_eq!(parse_suffix(""), Some(1));
+        assert_eq!(parse_suffix("b"), Some(512));
+        assert_eq!(parse_suffix("K"), Some(1024));
+        assert_eq!(parse_suffix("k"), Some(1024));
+        assert_eq!(parse_suffix("KiB"), Some(1024));
+        assert_eq!(parse_suffix("KB"), Some(1000));
+        assert_eq!(parse_suffix("kB"), Some(1000));
+        assert_eq!(parse_suffix("M"), Some(1024 * 1024));
+        assert_eq!(parse_suffix("MB"), Some(1000 * 1000));
+        assert_eq!(parse_suffix("GiB"), Some(1 << 30));
+        assert_eq!(parse_suffix("E"), Some(1 << 60));
+
+        // Anything else is not a suffix
+        assert_eq!(parse_suffix("B"), None);
+        assert_eq!(parse_suffix("KiBs"), None);
+        assert_eq!(parse_suffix("Ki"), None);
+        assert_eq!(parse_suffix("Z"), None);
+    }
+
+    #[test]
+    fn test_find_tail_start() {
+        let file = File::open("tests/inputs/one.txt").unwrap();
+        assert_eq!(find_tail_start(&file, 1, b'\n').unwrap(), 0);
+        assert_eq!(find_tail_start(&file, 2, b'\n').unwrap(), 0);
+
+        // ten.txt has ten lines in 49 bytes, the last one 4 bytes long
+        let file = File::open("tests/inputs/ten.txt").unwrap();
+        assert_eq!(find_tail_start(&file, 1, b'\n').unwrap(), 45);
+        assert_eq!(find_tail_start(&file, 10, b'\n').unwrap(), 0);
+        assert_eq!(find_tail_start(&file, 11, b'\n').unwrap(), 0);
+
+        // A last line without a newline counts as a line
+        assert_eq!(find_tail_start(Cursor::new("a\nb"), 1, b'\n').unwrap(), 2);
+        assert_eq!(find_tail_start(Cursor::new("a\nb\n"), 1, b'\n').unwrap(), 2);
+        assert_eq!(find_tail_start(Cursor::new("\n\n"), 1, b'\n').unwrap(), 1);
+        assert_eq!(find_tail_start(Cursor::new(""), 1, b'\n').unwrap(), 0);
+
+        // NUL-terminated records ignore newlines
+        assert_eq!(
+            find_tail_start(Cursor::new("a\nb\0c\0"), 1, b'\0').unwrap(),
+            4
+        );
+        assert_eq!(
+            find_tail_start(Cursor::new("a\nb\0c\0"), 2, b'\0').unwrap(),
+            0
+        );
+
+        // Lines spanning several blocks are found the same as by counting forward
+        let text: String = (0..5000)
+            .map(|i| format!("{}\n", "x".repeat(i % 97)))
+            .collect();
+        assert!(text.len() > 3 * BLOCK_SIZE);
+        for num in [1, 2, 100, 1234, 4999, 5000, 6000] {
+            let expected = text
+                .match_indices('\n')
+                .rev()
+                .nth(num)
+                .map_or(0, |(i, _)| i as u64 + 1);
+            assert_eq!(
+                find_tail_start(Cursor::new(&text), num as u64, b'\n').unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_skip_lines() {
+        let file = File::open("tests/inputs/ten.txt").unwrap();
+        assert_eq!(skip_lines(&file, 0, b'\n').unwrap(), 0);
+        assert_eq!(skip_lines(&file, 9, b'\n').unwrap(), 45);
+
+        // Skipping past the end stops at the end
+        assert_eq!(skip_lines(&file, 10, b'\n').unwrap(), 49);
+        assert_eq!(skip_lines(&file, 20, b'\n').unwrap(), 49);
+        assert_eq!(skip_lines(Cursor::new("a\nb"), 5, b'\n').unwrap(), 3);
+        assert_eq!(skip_lines(Cursor::new("a\nb\0c\0"), 1, b'\0').unwrap(), 4);
     }
 
     #[test]
@@ -1,8 +1,13 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
-use std::fs::{self, File};
-use std::io::Read;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Output, Stdio};
+use std::thread;
+use std::time::Duration;
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -12,6 +17,7 @@ const ONE: &str = "tests/inputs/one.txt";
 const TWO: &str = "tests/inputs/two.txt";
 const THREE: &str = "tests/inputs/three.txt";
 const TEN: &str = "tests/inputs/ten.txt";
+const ZERO: &str = "tests/inputs/zero.txt";
 
 // --------------------------------------------------
 fn random_string() -> String {
@@ -34,13 +40,8 @@ fn gen_bad_file() -> String {
 
 // --------------------------------------------------
 #[test]
-fn dies_no_args() -> TestResult {
-    Command::cargo_bin(PRG)?
-        .assert()
-        .failure()
-        .stderr(predicate::str::contains("USAGE"));
-
-    Ok(())
+fn no_args_reads_stdin() -> TestResult {
+    run_stdin(TEN, &[], "tests/expected/ten.txt.out")
 }
 
 // --------------------------------------------------
@@ -49,7 +50,7 @@ fn dies_bad_bytes() -> TestResult {
     let bad = random_string();
     let expected = format!("illegal byte count -- {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-c", &bad, EMPTY])
+        .args(["-c", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -63,7 +64,7 @@ fn dies_bad_lines() -> TestResult {
     let bad = random_string();
     let expected = format!("illegal line count -- {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-n", &bad, EMPTY])
+        .args(["-n", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -78,7 +79,7 @@ fn dies_bytes_and_lines() -> TestResult {
                used with '--bytes <BYTES>'";
 
     Command::cargo_bin(PRG)?
-        .args(&["-n", "1", "-c", "2"])
+        .args(["-n", "1", "-c", "2"])
         .assert()
         .failure()
         .stderr(predicate::str::contains(msg));
@@ -92,25 +93,64 @@ fn skips_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .args(&[ONE, &bad, TWO])
+        .args([ONE, &bad, TWO])
         .assert()
         .stderr(predicate::str::is_match(expected)?);
 
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn dies_bad_sleep_interval() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-s", "soon", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("illegal sleep interval -- soon"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pid() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "--pid", "init", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("illegal process id -- init"));
+
+    Ok(())
+}
+
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
+    // Bytes are copied as they are, even when a count splits a character
     let mut file = File::open(expected_file)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
-        .stdout(predicate::eq(expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(buffer.as_slice()));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+// Pipe the input in, where it can only be read once
+fn run_stdin(input_file: &str, args: &[&str], expected_file: &str) -> TestResult {
+    let input = fs::read(input_file)?;
+    let expected = fs::read(expected_file)?;
+
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
 
     Ok(())
 }
@@ -829,3 +869,338 @@ fn multiple_files_c_plus_3() -> TestResult {
         "tests/expected/all.c+3.out",
     )
 }
+
+// --------------------------------------------------
+// Follow a file in a fresh directory while `change` is made to it. The run
+// ends on its own through --pid, which names a process that exits after a second.
+fn follow(
+    args: &[&str],
+    initial: Option<&str>,
+    change: impl FnOnce(&Path) -> std::io::Result<()>,
+) -> Result<Output, Box<dyn std::error::Error>> {
+    follow_files(args, &[("log.txt", initial)], |dir| {
+        change(&dir.join("log.txt"))
+    })
+}
+
+// --------------------------------------------------
+// The same for several files, named in the order given, with `change`
+// getting their directory
+fn follow_files(
+    args: &[&str],
+    files: &[(&str, Option<&str>)],
+    change: impl FnOnce(&Path) -> std::io::Result<()>,
+) -> Result<Output, Box<dyn std::error::Error>> {
+    let dir = env::temp_dir().join(format!("tailr-{}", random_string()));
+    fs::create_dir(&dir)?;
+    for (name, initial) in files {
+        if let Some(initial) = initial {
+            fs::write(dir.join(name), initial)?;
+        }
+    }
+
+    let mut sleeper = std::process::Command::new("sleep").arg("1").spawn()?;
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .current_dir(&dir)
+        .args(args)
+        .args(["-s", "0.1", "--pid", &sleeper.id().to_string()])
+        .args(files.iter().map(|(name, _)| name))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    thread::sleep(Duration::from_millis(300));
+    change(&dir)?;
+    // reaped, as a zombie would still count as running
+    sleeper.wait()?;
+
+    let output = child.wait_with_output()?;
+    fs::remove_dir_all(&dir)?;
+    Ok(output)
+}
+
+// --------------------------------------------------
+fn append(path: &Path, text: &str) -> std::io::Result<()> {
+    OpenOptions::new()
+        .append(true)
+        .open(path)?
+        .write_all(text.as_bytes())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_appended() -> TestResult {
+    let output = follow(&["-f", "-n", "1"], Some("one\ntwo\n"), |path| {
+        append(path, "three\n")?;
+        thread::sleep(Duration::from_millis(200));
+        append(path, "four\n")
+    })?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, "two\nthree\nfour\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_from_end() -> TestResult {
+    // an empty tail still starts following at the end of the file
+    for flag in ["-n", "-c"] {
+        let output = follow(&["-f", flag, "0"], Some("one\n"), |path| {
+            append(path, "two\n")
+        })?;
+        assert_eq!(String::from_utf8(output.stdout)?, "two\n");
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_descriptor_rotation() -> TestResult {
+    // the renamed file is still the one being read
+    let output = follow(&["--follow=descriptor"], Some("old\n"), |path| {
+        let rotated = path.with_extension("1");
+        fs::rename(path, &rotated)?;
+        fs::write(path, "new\n")?;
+        append(&rotated, "more\n")
+    })?;
+    assert_eq!(String::from_utf8(output.stdout)?, "old\nmore\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_name_rotation() -> TestResult {
+    let output = follow(&["-F"], Some("old\n"), |path| {
+        fs::rename(path, path.with_extension("1"))?;
+        fs::write(path, "new\n")
+    })?;
+    assert_eq!(String::from_utf8(output.stdout)?, "old\nnew\n");
+    assert!(String::from_utf8(output.stderr)?.contains("following new file"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_truncated() -> TestResult {
+    let output = follow(&["-f"], Some("a long first line\n"), |path| {
+        fs::write(path, "short\n")
+    })?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "a long first line\nshort\n"
+    );
+    assert!(String::from_utf8(output.stderr)?.contains("file truncated"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_name_appears() -> TestResult {
+    let output = follow(&["--follow=name"], None, |path| fs::write(path, "hello\n"))?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(String::from_utf8(output.stdout)?, "hello\n");
+    assert!(stderr.contains("No such file or directory"));
+    assert!(stderr.contains("file has appeared"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn follow_name_unopenable() -> TestResult {
+    // a socket can be seen under the name but never opened
+    let output = follow(&["-F"], None, |path| {
+        let _socket = std::os::unix::net::UnixListener::bind(path)?;
+        thread::sleep(Duration::from_millis(300));
+        let replacement = path.with_extension("new");
+        fs::write(&replacement, "hello\n")?;
+        fs::rename(&replacement, path)
+    })?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(String::from_utf8(output.stdout)?, "hello\n");
+    assert_eq!(stderr.matches("No such device or address").count(), 1);
+    assert!(stderr.contains("file has become accessible"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_missing_file() -> TestResult {
+    // following by descriptor has nothing to wait for
+    Command::cargo_bin(PRG)?
+        .args(["-f", &gen_bad_file()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no files remaining"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_lines() -> TestResult {
+    for (args, expected) in [
+        (["-n", "3"], "tests/expected/ten.txt.n3.out"),
+        (["-n", "0"], "tests/expected/ten.txt.n0.out"),
+        (["-n", "+0"], "tests/expected/ten.txt.n+0.out"),
+        (["-n", "+2"], "tests/expected/ten.txt.n+2.out"),
+        (["-n", "200"], "tests/expected/ten.txt.n200.out"),
+    ] {
+        run_stdin(TEN, &args, expected)?;
+        run_stdin(TEN, &[&args[..], &["-"]].concat(), expected)?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_bytes() -> TestResult {
+    for (args, expected) in [
+        (["-c", "3"], "tests/expected/one.txt.c3.out"),
+        (["-c", "0"], "tests/expected/one.txt.c0.out"),
+        (["-c", "+0"], "tests/expected/one.txt.c+0.out"),
+        (["-c", "+2"], "tests/expected/one.txt.c+2.out"),
+        (["-c", "200"], "tests/expected/one.txt.c200.out"),
+    ] {
+        run_stdin(ONE, &args, expected)?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pipe_by_name() -> TestResult {
+    // a pipe opened by path gets the ring buffer too
+    run_stdin(
+        TEN,
+        &["-n", "4", "/dev/stdin"],
+        "tests/expected/ten.txt.n4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_header() -> TestResult {
+    let expected = format!(
+        "==> standard input <==\n{}\n==> {} <==\n{}",
+        fs::read_to_string("tests/expected/ten.txt.n1.out")?,
+        TWO,
+        fs::read_to_string("tests/expected/two.txt.n1.out")?
+    );
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "-", TWO])
+        .write_stdin(fs::read(TEN)?)
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_stdin_ends() -> TestResult {
+    // there is nothing to follow once a pipe has closed
+    run_stdin(TEN, &["-f", "-n", "3"], "tests/expected/ten.txt.n3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated() -> TestResult {
+    // records like those from `find -print0` may hold newlines
+    run(&["-z", "-n", "2", ZERO], "tests/expected/zero.txt.z.n2.out")?;
+    run(&["-z", "-n", "1", ZERO], "tests/expected/zero.txt.z.n1.out")?;
+    run(
+        &["--zero-terminated", "-n", "+3", ZERO],
+        "tests/expected/zero.txt.z.n+3.out",
+    )?;
+    run_stdin(ZERO, &["-z", "-n", "2"], "tests/expected/zero.txt.z.n2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn size_suffixes() -> TestResult {
+    // every suffix asks for more than the file has
+    for num in ["1b", "1K", "1kB", "1KiB", "2M", "1GB", "1E"] {
+        run(&["-c", num, TEN], "tests/expected/ten.txt.out")?;
+        run(&["-n", num, TEN], "tests/expected/ten.txt.out")?;
+    }
+    run(&["-c", "+0K", TEN], "tests/expected/ten.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_suffix() -> TestResult {
+    for (flag, what) in [("-c", "byte"), ("-n", "line")] {
+        Command::cargo_bin(PRG)?
+            .args([flag, "3Q", TEN])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(format!(
+                "illegal {} count -- 3Q",
+                what
+            )));
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn verbose_one_file() -> TestResult {
+    run(&["-v", ONE], "tests/expected/one.txt.v.out")?;
+    // the last of -q and -v wins
+    run(&["-q", "-v", ONE], "tests/expected/one.txt.v.out")?;
+    run(&["-v", "--quiet", ONE], "tests/expected/one.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_switches_headers() -> TestResult {
+    let pause = || thread::sleep(Duration::from_millis(200));
+    let output = follow_files(
+        &["-f"],
+        &[("a.log", Some("a0\n")), ("b.log", Some("b0\n"))],
+        |dir| {
+            // more from the last file shown needs no header
+            append(&dir.join("b.log"), "b1\n")?;
+            pause();
+            append(&dir.join("a.log"), "a1\n")?;
+            pause();
+            append(&dir.join("b.log"), "b2\n")
+        },
+    )?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "==> a.log <==\na0\n\n==> b.log <==\nb0\nb1\n\
+         \n==> a.log <==\na1\n\n==> b.log <==\nb2\n"
+    );
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_quiet_and_verbose() -> TestResult {
+    let change = |dir: &Path| {
+        append(&dir.join("a.log"), "a1\n")?;
+        thread::sleep(Duration::from_millis(200));
+        append(&dir.join("b.log"), "b1\n")
+    };
+    let files = [("a.log", Some("a0\n")), ("b.log", Some("b0\n"))];
+    let output = follow_files(&["-f", "-q"], &files, change)?;
+    assert_eq!(String::from_utf8(output.stdout)?, "a0\nb0\na1\nb1\n");
+
+    let output = follow_files(&["-f", "-v"], &files[..1], |dir| {
+        append(&dir.join("a.log"), "a1\n")
+    })?;
+    assert_eq!(String::from_utf8(output.stdout)?, "==> a.log <==\na0\na1\n");
+
+    Ok(())
+}